@@ -1,4 +1,12 @@
 pub mod assume_role;
+pub mod connector;
 pub mod create_session;
+pub mod credential_source;
+pub mod diagnosis;
+pub mod endpoint;
 pub mod raw_provider;
+pub mod retry;
+pub mod source_identity;
+pub mod sso;
 pub mod util;
+pub mod web_identity;
@@ -1,76 +1,318 @@
+use super::diagnosis::{diagnose, StsContext};
+use super::endpoint::sts_region;
 use super::util::*;
+use crate::client::credential_source;
 use crate::client::raw_provider::RawProvider;
+use crate::client::source_identity;
 use crate::error::Error::*;
 use crate::error::*;
-use crate::profile::Access::{AccessKey, AssumeRole};
-use crate::profile::{AssumedRole, Credential, Profile, ProfileMap};
-use rusoto_core::Region;
-use rusoto_sts::{AssumeRoleRequest, Sts, StsClient};
+use crate::profile::params::SessionParams;
+use crate::profile::Access::{
+    AccessKey, AssumeRole, CredentialProcess, CredentialSource, Sso, WebIdentity,
+};
+use crate::profile::{Arn, AssumedRole, Credential, Profile, ProfileMap};
+use rusoto_core::credential::StaticProvider;
+use rusoto_credential::{AwsCredentials, ProvideAwsCredentials};
+use rusoto_sts::{AssumeRoleRequest, Sts, StsClient, Tag};
 
+/// Credentials to call AssumeRole with.
+#[derive(Debug, PartialEq)]
+enum Source {
+    /// alive session of the source profile.
+    Session,
+    /// long-term keys of the source profile, with mfa token if the role has `mfa_serial`.
+    Keys { mfa: bool },
+}
+
+/// Assumes role of the profile. The source profile must be signed in or have access keys.
+/// `mfa_token` is taken if this hop uses it.
 pub async fn send(
     profile_name: &str,
     profile_map: &mut ProfileMap,
-    mfa_token: Option<String>,
+    mfa_token: &mut Option<String>,
 ) -> Result<()> {
+    let credential = assume(profile_name, profile_map, mfa_token).await?;
+    profile_map.get_mut(profile_name)?.credential = Some(credential);
+    Ok(())
+}
+
+/// Same as `send`, but returns the session instead of storing it, so that roles can be assumed concurrently.
+pub async fn assume(
+    profile_name: &str,
+    profile_map: &ProfileMap,
+    mfa_token: &mut Option<String>,
+) -> Result<Credential> {
     let profile = profile_map.get(profile_name)?;
     let access = &profile
         .access
         .as_ref()
         .ok_or(AssumeRoleSettingNotFound(profile.profile_name.clone()))?;
 
-    match access {
-        AccessKey(_) => Err(AssumeRoleSettingNotFound(profile.profile_name.clone())),
+    let (role_arn, mfa_serial, credentials, mfa_token, source) = match access {
+        AccessKey(_) | CredentialProcess(_) | Sso(_) | WebIdentity(_) => {
+            return Err(AssumeRoleSettingNotFound(profile.profile_name.clone()))
+        }
 
         AssumeRole(assume_role) => {
-            let source = profile_map.get(&assume_role.source_profile)?.clone();
-            let client = client(assume_role, &mfa_token, source)?;
-            let response = client.assume_role(request(assume_role, mfa_token)).await?;
-            let rusoto_credential = &response.credentials.ok_or(AwsResponseFormatError(
+            let source = profile_map.get(&assume_role.source_profile)?;
+            let selected = select_source(profile_name, assume_role, source, mfa_token)?;
+            let mfa_token = match selected {
+                Source::Keys { mfa: true } => mfa_token.take(),
+                _ => None,
+            };
+            let credentials = source_credentials(&selected, source).await?;
+            (
+                &assume_role.role_arn,
+                &assume_role.mfa_serial,
+                credentials,
+                mfa_token,
+                format!("profile {}", assume_role.source_profile),
+            )
+        }
+
+        CredentialSource(role) => {
+            let mfa_token = match role.mfa_serial {
+                Some(_) => Some(
+                    mfa_token
+                        .take()
+                        .ok_or_else(|| MfaTokenRequired(profile_name.to_owned()))?,
+                ),
+                None => None,
+            };
+            let credentials = credential_source::credentials(role.credential_source).await?;
+            (
+                &role.role_arn,
+                &role.mfa_serial,
+                credentials,
+                mfa_token,
+                format!("credential_source {:?}", role.credential_source),
+            )
+        }
+    };
+
+    let provider = StaticProvider::from(credentials);
+    let params = &profile.session_params;
+    let request = request(profile_name, role_arn, mfa_serial, params, mfa_token);
+    let region = sts_region(profile);
+    let context = StsContext {
+        operation: "AssumeRole",
+        profile: profile_name.to_owned(),
+        role_arn: Some(role_arn.to_string()),
+        mfa_serial: mfa_serial.clone(),
+        source: Some(source),
+        region: region.name().to_owned(),
+    };
+    let rusoto_credential = match &params.source_identity {
+        None => StsClient::new_with(http_client(&profile.http)?, provider, region)
+            .assume_role(request)
+            .await
+            .map_err(|e| diagnose(e, &context))?
+            .credentials
+            .ok_or(AwsResponseFormatError(
                 "no credential in AssumeRole response".to_string(),
-            ))?;
-            let profile = profile_map.get_mut(profile_name)?;
-            profile.credential = Some(Credential::new(rusoto_credential)?);
-            Ok(())
+            ))?,
+        Some(identity) => {
+            let http = &profile.http;
+            source_identity::assume_role(provider, &region, http, request, identity, &context)
+                .await?
         }
-    }
+    };
+    Credential::new(&rusoto_credential)
 }
 
-fn client(
+/// Signing in with mfa token uses long-term keys of the source profile.
+/// Otherwise the alive session of the source is used, or its keys if neither the role nor the keys have mfa.
+fn select_source(
+    profile_name: &str,
     assume_role: &AssumedRole,
-    mfa_token: &Option<String>,
     source: &Profile,
-) -> Result<StsClient> {
-    Ok(match mfa_token {
-        Some(_) => StsClient::new_with(
-            http_client()?,
-            profile_provider(&assume_role.source_profile)?,
-            Region::default(),
-        ),
-        _ => StsClient::new_with(
-            http_client()?,
-            RawProvider {
-                credential: source.credential.as_ref().unwrap().clone(),
-            },
-            Region::default(),
-        ),
+    mfa_token: &Option<String>,
+) -> Result<Source> {
+    let has_keys = matches!(
+        source.access,
+        Some(AccessKey(_)) | Some(CredentialProcess(_))
+    );
+    let has_session = source.credential.as_ref().is_some_and(|c| c.alive());
+    let needs_mfa = assume_role.mfa_serial.is_some();
+
+    if needs_mfa && has_keys && mfa_token.is_some() {
+        Ok(Source::Keys { mfa: true })
+    } else if has_session {
+        Ok(Source::Session)
+    } else if !has_keys {
+        Err(ProfileNotSignedIn(source.profile_name.clone()))
+    } else if needs_mfa {
+        Err(MfaTokenRequired(profile_name.to_owned()))
+    } else if source.mfa_serial().is_some() {
+        // the role inherits mfa of the source, which needs the session signed in with mfa.
+        Err(MfaTokenRequired(source.profile_name.clone()))
+    } else {
+        Ok(Source::Keys { mfa: false })
+    }
+}
+
+async fn source_credentials(selected: &Source, source: &Profile) -> Result<AwsCredentials> {
+    Ok(if let Source::Keys { .. } = selected {
+        long_term_credentials(source).await?
+    } else {
+        RawProvider {
+            credential: source
+                .credential
+                .as_ref()
+                .ok_or_else(|| ProfileNotSignedIn(source.profile_name.clone()))?
+                .clone(),
+        }
+        .credentials()
+        .await?
     })
 }
 
-fn request(assume_role: &AssumedRole, mfa_token: Option<String>) -> AssumeRoleRequest {
+fn request(
+    profile_name: &str,
+    role_arn: &Arn,
+    mfa_serial: &Option<String>,
+    params: &SessionParams,
+    mfa_token: Option<String>,
+) -> AssumeRoleRequest {
     AssumeRoleRequest {
-        duration_seconds: None,
-        external_id: None,
+        duration_seconds: params.duration_seconds,
+        external_id: params.external_id.clone(),
         policy: None,
         policy_arns: None,
-        role_arn: assume_role.role_arn.to_owned(),
-        role_session_name: "session_name".to_string(),
+        role_arn: role_arn.to_string(),
+        role_session_name: params.role_session_name(profile_name),
         serial_number: if mfa_token.is_some() {
-            Some(assume_role.mfa_serial.to_owned())
+            mfa_serial.to_owned()
         } else {
             None
         },
-        tags: None,
+        tags: if params.tags.is_empty() {
+            None
+        } else {
+            Some(
+                params
+                    .tags
+                    .iter()
+                    .map(|tag| Tag {
+                        key: tag.key.clone(),
+                        value: tag.value.clone(),
+                    })
+                    .collect(),
+            )
+        },
         token_code: mfa_token,
-        transitive_tag_keys: None,
+        transitive_tag_keys: if params.transitive_tag_keys.is_empty() {
+            None
+        } else {
+            Some(params.transitive_tag_keys.clone())
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::AccessKey as Keys;
+    use chrono::{Duration, Utc};
+
+    fn role(mfa_serial: Option<&str>) -> AssumedRole {
+        AssumedRole {
+            role_arn: "arn:aws:iam::000000000000:role/admin".parse().unwrap(),
+            mfa_serial: mfa_serial.map(|s| s.to_string()),
+            source_profile: "base".to_string(),
+        }
+    }
+
+    fn source(keys: bool, life: Option<Duration>) -> Profile {
+        Profile {
+            profile_name: "base".to_string(),
+            access: if keys {
+                Some(AccessKey(Keys::default()))
+            } else {
+                Some(AssumeRole(role(None)))
+            },
+            credential: life.map(|life| Credential {
+                access_key_id: "ASIA".to_string(),
+                expiration: Utc::now() + life,
+                secret_access_key: "secret".to_string(),
+                session_token: "token".to_string(),
+            }),
+            ..Profile::default()
+        }
+    }
+
+    fn select(mfa_serial: Option<&str>, source: Profile, token: Option<&str>) -> Result<Source> {
+        select_source(
+            "role",
+            &role(mfa_serial),
+            &source,
+            &token.map(|t| t.to_string()),
+        )
+    }
+
+    #[test]
+    fn mfa_token_is_used_with_keys() {
+        let mfa = Some("arn:aws:iam::000000000000:mfa/user");
+        let alive = Some(Duration::hours(1));
+        assert_eq!(
+            select(mfa, source(true, alive), Some("123456")).unwrap(),
+            Source::Keys { mfa: true }
+        );
+        assert_eq!(
+            select(mfa, source(true, alive), None).unwrap(),
+            Source::Session
+        );
+        assert!(matches!(
+            select(mfa, source(true, None), None),
+            Err(MfaTokenRequired(_))
+        ));
+    }
+
+    #[test]
+    fn role_without_mfa() {
+        assert_eq!(
+            select(None, source(true, None), None).unwrap(),
+            Source::Keys { mfa: false }
+        );
+        assert_eq!(
+            select(
+                None,
+                source(true, Some(Duration::hours(-1))),
+                Some("123456")
+            )
+            .unwrap(),
+            Source::Keys { mfa: false }
+        );
+    }
+
+    #[test]
+    fn role_inherits_mfa_of_keys() {
+        let keys_with_mfa = |life| Profile {
+            access: Some(AccessKey(Keys {
+                mfa_serial: Some("arn:aws:iam::000000000000:mfa/user".to_string()),
+                ..Keys::default()
+            })),
+            ..source(true, life)
+        };
+        assert!(matches!(
+            select(None, keys_with_mfa(None), Some("123456")),
+            Err(MfaTokenRequired(name)) if name == "base"
+        ));
+        assert_eq!(
+            select(None, keys_with_mfa(Some(Duration::hours(1))), None).unwrap(),
+            Source::Session
+        );
+    }
+
+    #[test]
+    fn chained_role_needs_alive_session() {
+        assert_eq!(
+            select(None, source(false, Some(Duration::hours(1))), None).unwrap(),
+            Source::Session
+        );
+        assert!(matches!(
+            select(None, source(false, Some(Duration::hours(-1))), None),
+            Err(ProfileNotSignedIn(_))
+        ));
     }
 }
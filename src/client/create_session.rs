@@ -1,10 +1,14 @@
+use super::diagnosis::{diagnose, StsContext};
+use super::endpoint::sts_region;
 use super::util::*;
 use crate::error::Error::*;
 use crate::error::*;
 use crate::profile::*;
-use rusoto_core::Region;
+use rusoto_core::credential::StaticProvider;
 use rusoto_sts::{GetSessionTokenRequest, Sts, StsClient};
 
+const DEFAULT_DURATION_SECONDS: i64 = 12 * 60 * 60;
+
 pub async fn send(profile: &mut Profile, token: &str) -> Result<()> {
     let serial_number;
     let access = profile.access.as_ref().ok_or(ProfileParamNotFound(
@@ -15,7 +19,13 @@ pub async fn send(profile: &mut Profile, token: &str) -> Result<()> {
         Access::AccessKey(key) => {
             serial_number = key.mfa_serial.as_ref();
         }
-        Access::AssumeRole(_) => {
+        Access::CredentialProcess(process) => {
+            serial_number = process.mfa_serial.as_ref();
+        }
+        Access::AssumeRole(_)
+        | Access::CredentialSource(_)
+        | Access::Sso(_)
+        | Access::WebIdentity(_) => {
             return Err(ProfileTypeError(
                 profile.profile_name.clone(),
                 "CreateSession".to_string(),
@@ -23,19 +33,43 @@ pub async fn send(profile: &mut Profile, token: &str) -> Result<()> {
         }
     }
 
+    let credentials = long_term_credentials(profile).await?;
+    if credentials.token().is_some() {
+        // credential_process may print a session, which GetSessionToken does not accept.
+        return Err(CredentialProcessError(format!(
+            "profile '{}' got a session token, GetSessionToken needs long-term keys. remove mfa_serial to use the session as it is.",
+            profile.profile_name
+        )));
+    }
     let client = StsClient::new_with(
-        http_client()?,
-        profile_provider(&profile.profile_name)?,
-        Region::default(),
+        http_client(&profile.http)?,
+        StaticProvider::from(credentials),
+        sts_region(profile),
     );
 
     let request = GetSessionTokenRequest {
-        duration_seconds: Some(12 * 60 * 60),
+        duration_seconds: Some(
+            profile
+                .session_params
+                .duration_seconds
+                .unwrap_or(DEFAULT_DURATION_SECONDS),
+        ),
         serial_number: serial_number.map(|s| s.to_owned()),
         token_code: Some(token.to_string()),
     };
 
-    let response = client.get_session_token(request).await?;
+    let context = StsContext {
+        operation: "GetSessionToken",
+        profile: profile.profile_name.clone(),
+        role_arn: None,
+        mfa_serial: serial_number.map(|s| s.to_owned()),
+        source: None,
+        region: sts_region(profile).name().to_owned(),
+    };
+    let response = client
+        .get_session_token(request)
+        .await
+        .map_err(|e| diagnose(e, &context))?;
     let rusoto_credential = &response.credentials.ok_or(AwsResponseFormatError(
         "no credential in GetSessionToken response".to_string(),
     ))?;
@@ -43,3 +77,74 @@ pub async fn send(profile: &mut Profile, token: &str) -> Result<()> {
     profile.credential = Some(Credential::new(rusoto_credential)?);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::StatusCode;
+
+    fn sts(_: hyper::http::request::Parts, body: String) -> (StatusCode, String) {
+        if !body.contains("Action=GetSessionToken") || !body.contains("TokenCode=123456") {
+            return (StatusCode::BAD_REQUEST, body);
+        }
+        (
+            StatusCode::OK,
+            r#"<GetSessionTokenResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <GetSessionTokenResult>
+    <Credentials>
+      <SessionToken>session token</SessionToken>
+      <SecretAccessKey>session secret</SecretAccessKey>
+      <Expiration>2999-01-02T03:04:05Z</Expiration>
+      <AccessKeyId>ASIASESSION</AccessKeyId>
+    </Credentials>
+  </GetSessionTokenResult>
+  <ResponseMetadata>
+    <RequestId>58c5dbae-abef-11e0-8cfe-09039844ac7d</RequestId>
+  </ResponseMetadata>
+</GetSessionTokenResponse>"#
+                .to_string(),
+        )
+    }
+
+    fn process_profile(command: &str) -> Profile {
+        Profile {
+            profile_name: "base".to_string(),
+            access: Some(Access::CredentialProcess(ProcessKeys {
+                command: command.to_string(),
+                mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+                timeout: std::time::Duration::from_secs(10),
+            })),
+            sts_endpoint: StsEndpoint {
+                override_url: Some(stand_in(sts)),
+                ..StsEndpoint::default()
+            },
+            ..Profile::default()
+        }
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn local_endpoint() {
+        let mut profile = process_profile(
+            r#"echo '{"Version":1,"AccessKeyId":"AKIA","SecretAccessKey":"secret"}'"#,
+        );
+        send(&mut profile, "123456").await.unwrap();
+        assert_eq!(
+            profile.credential.unwrap().access_key_id,
+            "ASIASESSION".to_string()
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn session_from_process() {
+        let mut profile = process_profile(
+            r#"echo '{"Version":1,"AccessKeyId":"ASIA","SecretAccessKey":"secret","SessionToken":"token"}'"#,
+        );
+        assert!(matches!(
+            send(&mut profile, "123456").await,
+            Err(CredentialProcessError(_))
+        ));
+        assert!(profile.credential.is_none());
+    }
+}
@@ -0,0 +1,288 @@
+use crate::env;
+use crate::error::Error::*;
+use crate::error::*;
+use crate::profile::CredentialSource;
+use chrono::{DateTime, FixedOffset, Utc};
+use hyper::{Body, Client, Method, Request};
+use rusoto_credential::AwsCredentials;
+use serde::Deserialize;
+
+const DEFAULT_IMDS_ENDPOINT: &str = "http://169.254.169.254";
+const IMDS_TOKEN_PATH: &str = "/latest/api/token";
+const IMDS_CREDENTIALS_PATH: &str = "/latest/meta-data/iam/security-credentials/";
+const IMDS_TOKEN_TTL: &str = "21600";
+const ECS_ENDPOINT: &str = "http://169.254.170.2";
+const TIMEOUT_SECONDS: u64 = 5;
+
+/// Response of instance metadata service and container credentials provider.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct SourceCredential {
+    access_key_id: String,
+    secret_access_key: String,
+    token: Option<String>,
+    expiration: Option<String>,
+}
+
+/// Credentials of the `credential_source` to assume role with.
+pub async fn credentials(source: CredentialSource) -> Result<AwsCredentials> {
+    resolve(source, &env::lookup).await
+}
+
+async fn resolve(
+    source: CredentialSource,
+    env: &(dyn Fn(&str) -> Option<String> + Sync),
+) -> Result<AwsCredentials> {
+    match source {
+        CredentialSource::Environment => environment(env),
+        CredentialSource::Ec2InstanceMetadata => {
+            if env("AWS_EC2_METADATA_DISABLED").is_some_and(|v| v == "true") {
+                return Err(CredentialSourceError(
+                    "AWS_EC2_METADATA_DISABLED is true.".to_string(),
+                ));
+            }
+            let endpoint = env("AWS_EC2_METADATA_SERVICE_ENDPOINT")
+                .unwrap_or_else(|| DEFAULT_IMDS_ENDPOINT.to_string());
+            instance_metadata(&endpoint).await
+        }
+        CredentialSource::EcsContainer => {
+            let uri = match env("AWS_CONTAINER_CREDENTIALS_RELATIVE_URI") {
+                Some(relative) => format!("{}{}", ECS_ENDPOINT, relative),
+                None => env("AWS_CONTAINER_CREDENTIALS_FULL_URI").ok_or_else(|| {
+                    CredentialSourceError(
+                        "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI nor AWS_CONTAINER_CREDENTIALS_FULL_URI is set."
+                            .to_string(),
+                    )
+                })?,
+            };
+            container(&uri, container_authorization_token(env)?).await
+        }
+    }
+}
+
+/// Keys in `AWS_ACCESS_KEY_ID` and others, as `EnvironmentProvider` reads them.
+fn environment(env: &dyn Fn(&str) -> Option<String>) -> Result<AwsCredentials> {
+    let key = |name: &str| {
+        env(name).ok_or_else(|| CredentialSourceError(format!("{} is not set.", name)))
+    };
+    SourceCredential {
+        access_key_id: key("AWS_ACCESS_KEY_ID")?,
+        secret_access_key: key("AWS_SECRET_ACCESS_KEY")?,
+        token: env("AWS_SESSION_TOKEN"),
+        expiration: env("AWS_CREDENTIAL_EXPIRATION"),
+    }
+    .aws_credentials()
+}
+
+fn container_authorization_token(env: &dyn Fn(&str) -> Option<String>) -> Result<Option<String>> {
+    match env("AWS_CONTAINER_AUTHORIZATION_TOKEN_FILE") {
+        Some(path) => std::fs::read_to_string(&path)
+            .map(|token| Some(token.trim().to_owned()))
+            .map_err(|e| CredentialSourceError(format!("{}: {}", path, e))),
+        None => Ok(env("AWS_CONTAINER_AUTHORIZATION_TOKEN")),
+    }
+}
+
+/// Role credentials of the instance with IMDSv2.
+async fn instance_metadata(endpoint: &str) -> Result<AwsCredentials> {
+    let endpoint = endpoint.trim_end_matches('/');
+    let token = fetch(
+        Request::builder()
+            .method(Method::PUT)
+            .uri(format!("{}{}", endpoint, IMDS_TOKEN_PATH))
+            .header("x-aws-ec2-metadata-token-ttl-seconds", IMDS_TOKEN_TTL),
+    )
+    .await?;
+
+    let get = |path: String| {
+        Request::builder()
+            .uri(format!("{}{}", endpoint, path))
+            .header("x-aws-ec2-metadata-token", token.as_str())
+    };
+    let roles = fetch(get(IMDS_CREDENTIALS_PATH.to_string())).await?;
+    let role = roles
+        .lines()
+        .next()
+        .filter(|role| !role.trim().is_empty())
+        .ok_or_else(|| CredentialSourceError("no role attached to the instance.".to_string()))?;
+    let body = fetch(get(format!("{}{}", IMDS_CREDENTIALS_PATH, role.trim()))).await?;
+    parse(&body)
+}
+
+async fn container(uri: &str, authorization_token: Option<String>) -> Result<AwsCredentials> {
+    let mut request = Request::builder().uri(uri);
+    if let Some(token) = authorization_token {
+        request = request.header(hyper::header::AUTHORIZATION, token);
+    }
+    parse(&fetch(request).await?)
+}
+
+/// The endpoints are local, so only http is supported.
+async fn fetch(request: hyper::http::request::Builder) -> Result<String> {
+    let request = request
+        .body(Body::empty())
+        .map_err(|e| CredentialSourceError(e.to_string()))?;
+    let uri = request.uri().to_string();
+    let error = |message: String| CredentialSourceError(format!("{}: {}", uri, message));
+    if request.uri().scheme_str() != Some("http") {
+        return Err(error("only http is supported.".to_string()));
+    }
+
+    let response = tokio::time::timeout(
+        std::time::Duration::from_secs(TIMEOUT_SECONDS),
+        Client::new().request(request),
+    )
+    .await
+    .map_err(|_| error("timed out.".to_string()))?
+    .map_err(|e| error(e.to_string()))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| error(e.to_string()))?;
+    let body = String::from_utf8_lossy(&body).to_string();
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(error(format!("{} {}", status, body)))
+    }
+}
+
+fn parse(body: &str) -> Result<AwsCredentials> {
+    let credential: SourceCredential = serde_json::from_str(body)
+        .map_err(|e| CredentialSourceError(format!("invalid credentials. {}", e)))?;
+    credential.aws_credentials()
+}
+
+impl SourceCredential {
+    fn aws_credentials(self) -> Result<AwsCredentials> {
+        let expiration = match self.expiration {
+            Some(expiration) => Some(
+                DateTime::<FixedOffset>::parse_from_rfc3339(&expiration)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .map_err(|e| CredentialSourceError(format!("invalid expiration. {}", e)))?,
+            ),
+            None => None,
+        };
+        Ok(AwsCredentials::new(
+            self.access_key_id,
+            self.secret_access_key,
+            self.token,
+            expiration,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::util::stand_in;
+    use hyper::http::request::Parts;
+    use hyper::StatusCode;
+
+    const CREDENTIAL: &str = r#"{"Code":"Success","Type":"AWS-HMAC","AccessKeyId":"ASIA","SecretAccessKey":"secret","Token":"token","Expiration":"2999-01-01T00:00:00Z"}"#;
+
+    fn imds(request: Parts, _: String) -> (StatusCode, String) {
+        let token = request
+            .headers
+            .get("x-aws-ec2-metadata-token")
+            .map(|v| v.to_str().unwrap().to_owned());
+        match (&request.method, request.uri.path(), token.as_deref()) {
+            (&Method::PUT, IMDS_TOKEN_PATH, _) => (StatusCode::OK, "imds-token".to_string()),
+            (_, _, Some(token)) if token != "imds-token" => {
+                (StatusCode::UNAUTHORIZED, "".to_string())
+            }
+            (_, IMDS_CREDENTIALS_PATH, Some(_)) => (StatusCode::OK, "ci-role\n".to_string()),
+            (_, "/latest/meta-data/iam/security-credentials/ci-role", Some(_)) => {
+                (StatusCode::OK, CREDENTIAL.to_string())
+            }
+            (_, _, None) => (StatusCode::UNAUTHORIZED, "".to_string()),
+            _ => (StatusCode::NOT_FOUND, "".to_string()),
+        }
+    }
+
+    fn ecs(request: Parts, _: String) -> (StatusCode, String) {
+        match request.headers.get(hyper::header::AUTHORIZATION) {
+            Some(token) if token == "auth" => (StatusCode::OK, CREDENTIAL.to_string()),
+            _ => (StatusCode::UNAUTHORIZED, "unauthorized".to_string()),
+        }
+    }
+
+    fn assert_credential(credentials: AwsCredentials) {
+        assert_eq!(credentials.aws_access_key_id(), "ASIA");
+        assert_eq!(credentials.aws_secret_access_key(), "secret");
+        assert_eq!(credentials.token().as_deref(), Some("token"));
+        assert_eq!(
+            credentials.expires_at().unwrap().to_rfc3339(),
+            "2999-01-01T00:00:00+00:00"
+        );
+    }
+
+    #[tokio::test]
+    async fn ec2_instance_metadata() {
+        let url = stand_in(imds);
+        assert_credential(instance_metadata(&url).await.unwrap());
+        assert_credential(instance_metadata(&format!("{}/", url)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn ec2_instance_metadata_without_role() {
+        let url = stand_in(|request, _| match request.method {
+            Method::PUT => (StatusCode::OK, "imds-token".to_string()),
+            _ => (StatusCode::NOT_FOUND, "".to_string()),
+        });
+        assert!(matches!(
+            instance_metadata(&url).await,
+            Err(CredentialSourceError(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn ecs_container() {
+        let url = stand_in(ecs);
+        let uri = format!("{}/credentials", url);
+        assert_credential(container(&uri, Some("auth".to_string())).await.unwrap());
+        assert!(container(&uri, None).await.is_err());
+        assert!(matches!(
+            container("https://localhost/credentials", None).await,
+            Err(CredentialSourceError(message)) if message.contains("only http")
+        ));
+    }
+
+    #[tokio::test]
+    async fn environment() {
+        let vars = env::fake(vec![
+            ("AWS_ACCESS_KEY_ID", "ASIA"),
+            ("AWS_SECRET_ACCESS_KEY", "secret"),
+            ("AWS_SESSION_TOKEN", "token"),
+            ("AWS_CREDENTIAL_EXPIRATION", "2999-01-01T00:00:00Z"),
+        ]);
+        assert_credential(resolve(CredentialSource::Environment, &vars).await.unwrap());
+        let vars = env::fake(vec![("AWS_ACCESS_KEY_ID", "ASIA")]);
+        assert!(resolve(CredentialSource::Environment, &vars).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn ecs_container_from_env() {
+        let uri = format!("{}/credentials", stand_in(ecs));
+        let vars = env::fake(vec![
+            ("AWS_CONTAINER_CREDENTIALS_FULL_URI", &uri),
+            ("AWS_CONTAINER_AUTHORIZATION_TOKEN", "auth"),
+        ]);
+        assert_credential(
+            resolve(CredentialSource::EcsContainer, &vars)
+                .await
+                .unwrap(),
+        );
+        let vars = env::fake(vec![("AWS_EC2_METADATA_DISABLED", "true")]);
+        assert!(resolve(CredentialSource::Ec2InstanceMetadata, &vars)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn invalid_response() {
+        assert!(parse("{}").is_err());
+        assert!(parse(r#"{"AccessKeyId":"A","SecretAccessKey":"s","Expiration":"x"}"#).is_err());
+        assert!(parse(r#"{"AccessKeyId":"A","SecretAccessKey":"s"}"#).is_ok());
+    }
+}
@@ -0,0 +1,246 @@
+use crate::error::Error::*;
+use crate::error::*;
+use rusoto_core::RusotoError;
+use std::fmt;
+
+/// What an STS request was for, to name the profile, the role and the mfa device in hints.
+#[derive(Clone, Debug, Default)]
+pub struct StsContext {
+    pub operation: &'static str,
+    pub profile: String,
+    pub role_arn: Option<String>,
+    pub mfa_serial: Option<String>,
+    /// where the credentials signing the request came from, e.g. `profile base`.
+    pub source: Option<String>,
+    pub region: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Cause {
+    MfaCodeInvalid,
+    AccessDenied,
+    RegionDisabled,
+    SourceSessionExpired,
+    ClockSkew,
+    InvalidAccessKey,
+    Unknown,
+}
+
+#[derive(Debug)]
+pub struct StsFailure {
+    pub cause: Cause,
+    pub message: String,
+    pub hint: Option<String>,
+    /// the response as it is, for `--verbose`.
+    pub raw: String,
+}
+
+impl fmt::Display for StsFailure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\nhint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Classifies the error of the STS request. Requests which didn't reach STS are `StsUnreachable`,
+/// so that the cached session can be used instead.
+pub fn diagnose<E: std::error::Error + 'static>(
+    error: RusotoError<E>,
+    context: &StsContext,
+) -> Error {
+    let (code, message, raw) = match error {
+        RusotoError::HttpDispatch(e) => return StsUnreachable(e.to_string()),
+        RusotoError::Service(e) => {
+            let raw = format!("{:?}", e);
+            let code = raw.split('(').next().unwrap_or_default().to_owned();
+            (code, e.to_string(), raw)
+        }
+        RusotoError::Unknown(response) => {
+            let body = response.body_as_str();
+            (
+                element(body, "Code").unwrap_or_default(),
+                element(body, "Message").unwrap_or_else(|| response.status.to_string()),
+                format!("{}\n{}", response.status, body),
+            )
+        }
+        e => (String::new(), e.to_string(), format!("{:?}", e)),
+    };
+    let cause = classify(&code, &message);
+    let code = if code.is_empty() {
+        code
+    } else {
+        format!("{}: ", code)
+    };
+    StsFailed(Box::new(StsFailure {
+        cause,
+        message: format!(
+            "{} for profile {} failed. {}{}",
+            context.operation, context.profile, code, message
+        ),
+        hint: hint(cause, context),
+        raw,
+    }))
+}
+
+fn classify(code: &str, message: &str) -> Cause {
+    if message.contains("MultiFactorAuthentication") {
+        Cause::MfaCodeInvalid
+    } else if code.starts_with("ExpiredToken")
+        || message.contains("token included in the request is expired")
+    {
+        Cause::SourceSessionExpired
+    } else if code == "RequestExpired"
+        || code == "RequestTimeTooSkewed"
+        || message.contains("Signature expired")
+    {
+        Cause::ClockSkew
+    } else if [
+        "InvalidClientTokenId",
+        "SignatureDoesNotMatch",
+        "UnrecognizedClientException",
+    ]
+    .contains(&code)
+    {
+        Cause::InvalidAccessKey
+    } else if code.starts_with("RegionDisabled") {
+        Cause::RegionDisabled
+    } else if code == "AccessDenied" {
+        Cause::AccessDenied
+    } else {
+        Cause::Unknown
+    }
+}
+
+fn hint(cause: Cause, context: &StsContext) -> Option<String> {
+    let profile = &context.profile;
+    let source = context
+        .source
+        .clone()
+        .unwrap_or_else(|| format!("profile {}", profile));
+    match cause {
+        Cause::MfaCodeInvalid => Some(format!(
+            "the code of {} was wrong or already used, or the clock of the device is off. Sign in with `ngydv in {}` and a new code.",
+            context.mfa_serial.as_deref().unwrap_or("the mfa device"),
+            profile
+        )),
+        Cause::AccessDenied => Some(match (&context.role_arn, &context.mfa_serial) {
+            (Some(role_arn), Some(serial)) => format!(
+                "{} may not assume {}. Check the trust policy of the role, sts:AssumeRole permission of the source, and that the policy accepts mfa of {}.",
+                source, role_arn, serial
+            ),
+            (Some(role_arn), None) => format!(
+                "{} may not assume {}. Check the trust policy of the role, and sts:AssumeRole permission of the source. Set `mfa_serial` if the policy requires mfa.",
+                source, role_arn
+            ),
+            (None, _) => format!("{} is not allowed to call {}.", source, context.operation),
+        }),
+        Cause::RegionDisabled => Some(format!(
+            "STS is not activated in {}. Activate the region in the account settings of IAM, or change `region` of profile {}.",
+            context.region, profile
+        )),
+        Cause::SourceSessionExpired => Some(match source.strip_prefix("profile ") {
+            Some(name) => format!(
+                "the session of profile {0} has expired. Sign in again with `ngydv in {0}`.",
+                name
+            ),
+            None => format!("the credentials of {} have expired.", source),
+        }),
+        Cause::ClockSkew => Some(
+            "the clock of this machine is off from AWS. Synchronize it, e.g. with NTP.".to_string(),
+        ),
+        Cause::InvalidAccessKey => Some(format!(
+            "the access key of {} is wrong or deactivated. Check aws_access_key_id and aws_secret_access_key.",
+            source
+        )),
+        Cause::Unknown => None,
+    }
+}
+
+fn element(body: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&close)?;
+    Some(body[start..start + end].trim().to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::request::BufferedHttpResponse;
+    use rusoto_sts::AssumeRoleError;
+
+    fn context() -> StsContext {
+        StsContext {
+            operation: "AssumeRole",
+            profile: "admin".to_string(),
+            role_arn: Some("arn:aws:iam::000000000000:role/admin".to_string()),
+            mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+            source: Some("profile base".to_string()),
+            region: "ap-east-1".to_string(),
+        }
+    }
+
+    fn failure(status: u16, code: &str, message: &str) -> StsFailure {
+        let body = format!(
+            "<ErrorResponse><Error><Type>Sender</Type><Code>{}</Code><Message>{}</Message></Error></ErrorResponse>",
+            code, message
+        );
+        let error = RusotoError::<AssumeRoleError>::Unknown(BufferedHttpResponse {
+            status: hyper::StatusCode::from_u16(status).unwrap(),
+            body: body.into(),
+            headers: Default::default(),
+        });
+        match diagnose(error, &context()) {
+            StsFailed(failure) => *failure,
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn causes() {
+        let cases = vec![
+            (403, "AccessDenied", "MultiFactorAuthentication failed with invalid MFA one time pass code.", Cause::MfaCodeInvalid),
+            (403, "AccessDenied", "User: arn:aws:iam::000000000000:user/me is not authorized to perform: sts:AssumeRole", Cause::AccessDenied),
+            (403, "ExpiredToken", "The security token included in the request is expired", Cause::SourceSessionExpired),
+            (403, "SignatureDoesNotMatch", "Signature expired: 20200101T000000Z is now earlier than 20200101T001000Z", Cause::ClockSkew),
+            (403, "InvalidClientTokenId", "The security token included in the request is invalid.", Cause::InvalidAccessKey),
+            (403, "RegionDisabledException", "STS is not activated in this region for account:000000000000.", Cause::RegionDisabled),
+            (400, "ValidationError", "1 validation error detected", Cause::Unknown),
+        ];
+        for (status, code, message, cause) in cases {
+            assert_eq!(failure(status, code, message).cause, cause, "{}", code);
+        }
+    }
+
+    #[test]
+    fn message_and_hint() {
+        let failure = failure(
+            403,
+            "AccessDenied",
+            "User: arn:aws:iam::000000000000:user/me is not authorized to perform: sts:AssumeRole",
+        );
+        assert!(failure
+            .message
+            .starts_with("AssumeRole for profile admin failed. AccessDenied: User:"));
+        let hint = failure.hint.as_ref().unwrap();
+        assert!(hint.contains("profile base may not assume arn:aws:iam::000000000000:role/admin"));
+        assert!(hint.contains("arn:aws:iam::000000000000:mfa/me"));
+        assert!(failure.raw.contains("<Type>Sender</Type>"));
+        assert!(!failure.to_string().contains("<Type>"));
+
+        let service = RusotoError::Service(AssumeRoleError::RegionDisabled(
+            "STS is not activated in this region".to_string(),
+        ));
+        match diagnose(service, &context()) {
+            StsFailed(failure) => {
+                assert_eq!(failure.cause, Cause::RegionDisabled);
+                assert!(failure.hint.unwrap().contains("ap-east-1"));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
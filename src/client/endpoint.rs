@@ -0,0 +1,239 @@
+use crate::env;
+use crate::profile::{Partition, Profile, StsEndpoint};
+use rusoto_core::Region;
+
+/// Regions whose `legacy` endpoint is the global one, sts.amazonaws.com.
+const LEGACY_GLOBAL_REGIONS: [&str; 16] = [
+    "ap-northeast-1",
+    "ap-south-1",
+    "ap-southeast-1",
+    "ap-southeast-2",
+    "aws-global",
+    "ca-central-1",
+    "eu-central-1",
+    "eu-north-1",
+    "eu-west-1",
+    "eu-west-2",
+    "eu-west-3",
+    "sa-east-1",
+    "us-east-1",
+    "us-east-2",
+    "us-west-1",
+    "us-west-2",
+];
+
+/// Region and endpoint to send STS requests of the profile to.
+pub fn sts_region(profile: &Profile) -> Region {
+    resolve(
+        &profile.sts_endpoint,
+        profile.region.as_deref(),
+        profile.partition(),
+        &env::lookup,
+    )
+}
+
+/// In the order of `--endpoint-url`, `AWS_ENDPOINT_URL_STS`, `AWS_ENDPOINT_URL`, `endpoint_url` of the profile,
+/// and the endpoint of the region, which is regional unless `sts_regional_endpoints` is `legacy`.
+/// Regions in environment variables of another partition are ignored, as `aws-cn` and `aws-us-gov`
+/// have no global endpoint and their sessions are signed for their own regions.
+fn resolve(
+    endpoint: &StsEndpoint,
+    region: Option<&str>,
+    partition: Option<Partition>,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Region {
+    let in_partition = |region: &String| {
+        partition.is_none_or(|partition| Partition::of_region(region) == partition)
+    };
+    let region = region
+        .map(|region| region.to_owned())
+        .or_else(|| env("AWS_REGION").filter(in_partition))
+        .or_else(|| env("AWS_DEFAULT_REGION").filter(in_partition))
+        .or_else(|| match partition {
+            Some(Partition::Aws) | None => None,
+            Some(partition) => Some(partition.default_region().to_owned()),
+        });
+    let ignore_configured = env("AWS_IGNORE_CONFIGURED_ENDPOINT_URLS")
+        .is_some_and(|value| value.eq_ignore_ascii_case("true"));
+    let configured = if ignore_configured {
+        None
+    } else {
+        env("AWS_ENDPOINT_URL_STS")
+            .or_else(|| env("AWS_ENDPOINT_URL"))
+            .or_else(|| endpoint.endpoint_url.clone())
+    };
+    if let Some(url) = endpoint.override_url.clone().or(configured) {
+        return Region::Custom {
+            name: region.unwrap_or_else(|| "us-east-1".to_string()),
+            endpoint: url,
+        };
+    }
+
+    let legacy = endpoint
+        .regional
+        .clone()
+        .or_else(|| env("AWS_STS_REGIONAL_ENDPOINTS"))
+        .is_some_and(|value| value.eq_ignore_ascii_case("legacy"));
+    match region {
+        Some(region) if !(legacy && LEGACY_GLOBAL_REGIONS.contains(&region.as_str())) => {
+            let suffix = Partition::of_region(&region).dns_suffix();
+            Region::Custom {
+                endpoint: format!("https://sts.{}.{}", region, suffix),
+                name: region,
+            }
+        }
+        _ => Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: "https://sts.amazonaws.com".to_string(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve_with(
+        endpoint: &StsEndpoint,
+        region: Option<&str>,
+        vars: Vec<(&str, &str)>,
+    ) -> (String, String) {
+        resolve_in(endpoint, region, None, vars)
+    }
+
+    fn resolve_in(
+        endpoint: &StsEndpoint,
+        region: Option<&str>,
+        partition: Option<Partition>,
+        vars: Vec<(&str, &str)>,
+    ) -> (String, String) {
+        match resolve(endpoint, region, partition, &env::fake(vars)) {
+            Region::Custom { name, endpoint } => (name, endpoint),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    fn pair(name: &str, endpoint: &str) -> (String, String) {
+        (name.to_string(), endpoint.to_string())
+    }
+
+    #[test]
+    fn regional_endpoint() {
+        let endpoint = StsEndpoint::default();
+        assert_eq!(
+            resolve_with(&endpoint, Some("ap-east-1"), vec![]),
+            pair("ap-east-1", "https://sts.ap-east-1.amazonaws.com")
+        );
+        assert_eq!(
+            resolve_with(&endpoint, None, vec![("AWS_REGION", "eu-south-1")]),
+            pair("eu-south-1", "https://sts.eu-south-1.amazonaws.com")
+        );
+        assert_eq!(
+            resolve_with(&endpoint, Some("cn-north-1"), vec![]),
+            pair("cn-north-1", "https://sts.cn-north-1.amazonaws.com.cn")
+        );
+        assert_eq!(
+            resolve_with(&endpoint, None, vec![]),
+            pair("us-east-1", "https://sts.amazonaws.com")
+        );
+    }
+
+    #[test]
+    fn legacy_endpoint() {
+        let legacy = StsEndpoint {
+            regional: Some("legacy".to_string()),
+            ..StsEndpoint::default()
+        };
+        assert_eq!(
+            resolve_with(&legacy, Some("ap-northeast-1"), vec![]),
+            pair("us-east-1", "https://sts.amazonaws.com")
+        );
+        // opt-in regions have no global endpoint even in legacy mode.
+        assert_eq!(
+            resolve_with(&legacy, Some("ap-east-1"), vec![]),
+            pair("ap-east-1", "https://sts.ap-east-1.amazonaws.com")
+        );
+        assert_eq!(
+            resolve_with(
+                &StsEndpoint::default(),
+                Some("us-west-2"),
+                vec![("AWS_STS_REGIONAL_ENDPOINTS", "legacy")]
+            ),
+            pair("us-east-1", "https://sts.amazonaws.com")
+        );
+    }
+
+    #[test]
+    fn endpoint_url() {
+        let configured = StsEndpoint {
+            endpoint_url: Some("https://sts.internal".to_string()),
+            ..StsEndpoint::default()
+        };
+        assert_eq!(
+            resolve_with(&configured, Some("eu-west-1"), vec![]),
+            pair("eu-west-1", "https://sts.internal")
+        );
+        assert_eq!(
+            resolve_with(
+                &configured,
+                Some("eu-west-1"),
+                vec![
+                    ("AWS_ENDPOINT_URL", "https://all.internal"),
+                    ("AWS_ENDPOINT_URL_STS", "https://sts.env.internal")
+                ]
+            ),
+            pair("eu-west-1", "https://sts.env.internal")
+        );
+        assert_eq!(
+            resolve_with(
+                &configured,
+                Some("eu-west-1"),
+                vec![("AWS_IGNORE_CONFIGURED_ENDPOINT_URLS", "true")]
+            ),
+            pair("eu-west-1", "https://sts.eu-west-1.amazonaws.com")
+        );
+        let overridden = StsEndpoint {
+            override_url: Some("http://127.0.0.1:4566".to_string()),
+            ..configured
+        };
+        assert_eq!(
+            resolve_with(
+                &overridden,
+                None,
+                vec![("AWS_ENDPOINT_URL_STS", "https://sts.env.internal")]
+            ),
+            pair("us-east-1", "http://127.0.0.1:4566")
+        );
+    }
+
+    #[test]
+    fn partition_endpoint() {
+        let endpoint = StsEndpoint::default();
+        assert_eq!(
+            resolve_in(&endpoint, None, Some(Partition::AwsCn), vec![]),
+            pair("cn-north-1", "https://sts.cn-north-1.amazonaws.com.cn")
+        );
+        assert_eq!(
+            resolve_in(
+                &endpoint,
+                None,
+                Some(Partition::AwsUsGov),
+                vec![("AWS_REGION", "ap-northeast-1")]
+            ),
+            pair("us-gov-west-1", "https://sts.us-gov-west-1.amazonaws.com")
+        );
+        assert_eq!(
+            resolve_in(
+                &endpoint,
+                None,
+                Some(Partition::AwsUsGov),
+                vec![("AWS_REGION", "us-gov-east-1")]
+            ),
+            pair("us-gov-east-1", "https://sts.us-gov-east-1.amazonaws.com")
+        );
+        assert_eq!(
+            resolve_in(&endpoint, None, Some(Partition::Aws), vec![]),
+            pair("us-east-1", "https://sts.amazonaws.com")
+        );
+    }
+}
@@ -0,0 +1,398 @@
+use super::connector::{connector, Connector};
+use crate::env;
+use crate::error::Error::*;
+use crate::error::*;
+use crate::profile::HttpSettings;
+use rand::Rng;
+use rusoto_core::request::{
+    BufferedHttpResponse, DispatchSignedRequest, DispatchSignedRequestFuture, HttpDispatchError,
+    HttpResponse,
+};
+use rusoto_core::signature::{SignedRequest, SignedRequestPayload};
+use rusoto_core::{ByteStream, HttpClient};
+use std::sync::Arc;
+use std::time::Duration;
+
+pub const RETRY_MODES: [&str; 3] = ["legacy", "standard", "adaptive"];
+
+/// Seconds to wait for the whole response of a request, unless `read_timeout` is set.
+const DEFAULT_READ_TIMEOUT: u64 = 30;
+const BASE_DELAY: Duration = Duration::from_secs(1);
+const MAX_DELAY: Duration = Duration::from_secs(20);
+
+/// Error codes of throttling, which AWS SDKs retry.
+const THROTTLING_CODES: [&str; 9] = [
+    "Throttling",
+    "ThrottlingException",
+    "ThrottledException",
+    "RequestThrottledException",
+    "TooManyRequestsException",
+    "RequestLimitExceeded",
+    "RequestThrottled",
+    "PriorRequestNotComplete",
+    "IDPCommunicationError",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// attempts including the first request.
+    pub max_attempts: u32,
+    pub read_timeout: Duration,
+    pub base_delay: Duration,
+}
+
+impl RetryPolicy {
+    pub fn new(settings: &HttpSettings) -> Result<Self> {
+        Self::resolve(settings, &env::lookup)
+    }
+
+    /// `AWS_RETRY_MODE` and `AWS_MAX_ATTEMPTS` are preferred to `retry_mode` and `max_attempts` of the profile.
+    /// `legacy` mode attempts 5 times, and `standard` 3 times. `adaptive` is same as `standard`,
+    /// as ngydv sends too few requests for client side rate limiting.
+    fn resolve(settings: &HttpSettings, env: &dyn Fn(&str) -> Option<String>) -> Result<Self> {
+        let (key, mode) = match env("AWS_RETRY_MODE") {
+            Some(mode) => ("AWS_RETRY_MODE", mode),
+            None => (
+                "retry_mode",
+                settings
+                    .retry_mode
+                    .clone()
+                    .unwrap_or_else(|| "standard".to_string()),
+            ),
+        };
+        let default_attempts = match mode.as_str() {
+            "legacy" => 5,
+            "standard" | "adaptive" => 3,
+            _ => {
+                return Err(HttpClientError(format!(
+                    "{} {:?} is not legacy, standard nor adaptive.",
+                    key, mode
+                )))
+            }
+        };
+        let max_attempts = match env("AWS_MAX_ATTEMPTS") {
+            Some(value) => value
+                .parse()
+                .ok()
+                .filter(|attempts| *attempts > 0)
+                .ok_or_else(|| {
+                    HttpClientError(format!(
+                        "AWS_MAX_ATTEMPTS {:?} is not a positive number.",
+                        value
+                    ))
+                })?,
+            None => settings.max_attempts.unwrap_or(default_attempts),
+        };
+        Ok(Self {
+            max_attempts,
+            read_timeout: Duration::from_secs(
+                settings.read_timeout.unwrap_or(DEFAULT_READ_TIMEOUT),
+            ),
+            base_delay: BASE_DELAY,
+        })
+    }
+
+    /// Exponential backoff with full jitter, a random delay up to `base_delay * 2^(attempt - 1)` and 20 seconds.
+    fn backoff(&self, attempt: u32) -> Duration {
+        let ceiling = self
+            .base_delay
+            .checked_mul(2u32.saturating_pow(attempt - 1))
+            .map_or(MAX_DELAY, |delay| delay.min(MAX_DELAY));
+        ceiling.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Sends requests with `HttpClient`, retrying connection errors, timeouts, throttling and 5xx errors.
+/// Requests with a one-time mfa code are retried only when they could not connect, or STS answered,
+/// since a timed out request may have used the code.
+pub struct RetryClient {
+    http: Arc<HttpClient<Connector>>,
+    policy: RetryPolicy,
+}
+
+impl RetryClient {
+    pub fn new(settings: &HttpSettings) -> Result<Self> {
+        Ok(Self {
+            http: Arc::new(HttpClient::from_connector(connector(settings)?)),
+            policy: RetryPolicy::new(settings)?,
+        })
+    }
+}
+
+impl DispatchSignedRequest for RetryClient {
+    fn dispatch(
+        &self,
+        request: SignedRequest,
+        _timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        let http = self.http.clone();
+        let policy = self.policy;
+        Box::pin(async move {
+            // streams can't be sent twice, though STS requests are never streamed.
+            if let Some(SignedRequestPayload::Stream(_)) = request.payload {
+                return http.dispatch(request, Some(policy.read_timeout)).await;
+            }
+            let one_time = one_time_code(&request);
+            let mut attempt = 1;
+            loop {
+                let result = send(&http, copy(&request), policy.read_timeout).await;
+                let retry = match &result {
+                    Ok(response) => retryable(response),
+                    Err(e) => !one_time || not_connected(e),
+                };
+                if !retry || attempt >= policy.max_attempts {
+                    return match result {
+                        Ok(response) => Ok(HttpResponse {
+                            status: response.status,
+                            body: ByteStream::from(response.body.to_vec()),
+                            headers: response.headers,
+                        }),
+                        Err(e) if attempt > 1 => Err(HttpDispatchError::new(format!(
+                            "{} ({} attempts)",
+                            e, attempt
+                        ))),
+                        Err(e) => Err(e),
+                    };
+                }
+                tokio::time::delay_for(policy.backoff(attempt)).await;
+                attempt += 1;
+            }
+        })
+    }
+}
+
+async fn send(
+    http: &HttpClient<Connector>,
+    request: SignedRequest,
+    timeout: Duration,
+) -> std::result::Result<BufferedHttpResponse, HttpDispatchError> {
+    let host = request.hostname();
+    let response = async {
+        let mut response = http.dispatch(request, None).await?;
+        response.buffer().await
+    };
+    match tokio::time::timeout(timeout, response).await {
+        Ok(result) => result.map_err(|e| HttpDispatchError::new(format!("{}: {}", host, e))),
+        Err(_) => Err(HttpDispatchError::new(format!(
+            "{}: no response in {:?}",
+            host, timeout
+        ))),
+    }
+}
+
+fn retryable(response: &BufferedHttpResponse) -> bool {
+    let status = response.status.as_u16();
+    let body = response.body_as_str();
+    status == 429
+        || [500, 502, 503, 504].contains(&status)
+        || status == 400
+            && THROTTLING_CODES
+                .iter()
+                .any(|code| body.contains(&format!("<Code>{}</Code>", code)))
+}
+
+fn one_time_code(request: &SignedRequest) -> bool {
+    let in_payload = match &request.payload {
+        Some(SignedRequestPayload::Buffer(bytes)) => {
+            let body = String::from_utf8_lossy(bytes);
+            body.contains("TokenCode=") || body.contains("SerialNumber=")
+        }
+        _ => false,
+    };
+    in_payload
+        || request.params.contains_key("TokenCode")
+        || request.params.contains_key("SerialNumber")
+}
+
+/// hyper fails with this before anything is sent.
+fn not_connected(error: &HttpDispatchError) -> bool {
+    error.to_string().contains("error trying to connect")
+}
+
+/// Same request with a buffered payload. The signature stays valid for 5 minutes.
+fn copy(request: &SignedRequest) -> SignedRequest {
+    SignedRequest {
+        method: request.method.clone(),
+        service: request.service.clone(),
+        region: request.region.clone(),
+        path: request.path.clone(),
+        headers: request.headers.clone(),
+        params: request.params.clone(),
+        scheme: request.scheme.clone(),
+        hostname: request.hostname.clone(),
+        payload: match &request.payload {
+            Some(SignedRequestPayload::Buffer(bytes)) => {
+                Some(SignedRequestPayload::Buffer(bytes.clone()))
+            }
+            _ => None,
+        },
+        canonical_query_string: request.canonical_query_string.clone(),
+        canonical_uri: request.canonical_uri.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::util::stand_in;
+    use hyper::http::request::Parts;
+    use hyper::StatusCode;
+    use rusoto_core::Region;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn resolve(settings: &HttpSettings, vars: Vec<(&str, &str)>) -> Result<RetryPolicy> {
+        RetryPolicy::resolve(settings, &env::fake(vars))
+    }
+
+    #[test]
+    fn retry_policy() {
+        assert_eq!(
+            resolve(&HttpSettings::default(), vec![]).unwrap(),
+            RetryPolicy {
+                max_attempts: 3,
+                read_timeout: Duration::from_secs(30),
+                base_delay: BASE_DELAY,
+            }
+        );
+        let configured = HttpSettings {
+            retry_mode: Some("legacy".to_string()),
+            read_timeout: Some(5),
+            ..HttpSettings::default()
+        };
+        let policy = resolve(&configured, vec![]).unwrap();
+        assert_eq!(policy.max_attempts, 5);
+        assert_eq!(policy.read_timeout, Duration::from_secs(5));
+        let policy = resolve(&configured, vec![("AWS_RETRY_MODE", "adaptive")]).unwrap();
+        assert_eq!(policy.max_attempts, 3);
+        let policy = resolve(&configured, vec![("AWS_MAX_ATTEMPTS", "1")]).unwrap();
+        assert_eq!(policy.max_attempts, 1);
+        assert!(resolve(&configured, vec![("AWS_MAX_ATTEMPTS", "0")]).is_err());
+        assert!(matches!(
+            resolve(&configured, vec![("AWS_RETRY_MODE", "fast")]),
+            Err(HttpClientError(message)) if message.starts_with("AWS_RETRY_MODE")
+        ));
+        let unknown = HttpSettings {
+            retry_mode: Some("fast".to_string()),
+            ..HttpSettings::default()
+        };
+        assert!(matches!(
+            resolve(&unknown, vec![]),
+            Err(HttpClientError(message)) if message.starts_with("retry_mode")
+        ));
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = resolve(&HttpSettings::default(), vec![]).unwrap();
+        for _ in 0..100 {
+            assert!(policy.backoff(1) < Duration::from_secs(1));
+            assert!(policy.backoff(3) < Duration::from_secs(4));
+            assert!(policy.backoff(40) < MAX_DELAY);
+        }
+    }
+
+    static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Throttles the first request, fails the second and answers the third.
+    fn flaky(_: Parts, _: String) -> (StatusCode, String) {
+        match REQUESTS.fetch_add(1, Ordering::SeqCst) {
+            0 => (
+                StatusCode::BAD_REQUEST,
+                "<ErrorResponse><Error><Code>Throttling</Code></Error></ErrorResponse>".to_string(),
+            ),
+            1 => (StatusCode::SERVICE_UNAVAILABLE, "".to_string()),
+            _ => (StatusCode::OK, "answered".to_string()),
+        }
+    }
+
+    fn client(max_attempts: u32, read_timeout: Duration) -> RetryClient {
+        RetryClient {
+            http: Arc::new(HttpClient::from_connector(
+                connector(&HttpSettings::default()).unwrap(),
+            )),
+            policy: RetryPolicy {
+                max_attempts,
+                read_timeout,
+                base_delay: Duration::from_millis(10),
+            },
+        }
+    }
+
+    fn request(url: String) -> SignedRequest {
+        with_payload(url, "Action=GetCallerIdentity")
+    }
+
+    fn with_payload(url: String, payload: &'static str) -> SignedRequest {
+        let region = Region::Custom {
+            name: "us-east-1".to_string(),
+            endpoint: url,
+        };
+        let mut request = SignedRequest::new("POST", "sts", &region, "/");
+        request.set_payload(Some(payload));
+        request
+    }
+
+    #[tokio::test]
+    async fn retry_until_answered() {
+        let url = stand_in(flaky);
+        let mut response = client(3, Duration::from_secs(5))
+            .dispatch(request(url), None)
+            .await
+            .unwrap();
+        let response = response.buffer().await.unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.body_as_str(), "answered");
+        assert_eq!(REQUESTS.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn unreachable() {
+        // nothing listens on the port of the closed listener.
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let error = client(2, Duration::from_secs(5))
+            .dispatch(request(url), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().ends_with("(2 attempts)"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn read_timeout() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let error = client(1, Duration::from_millis(200))
+            .dispatch(request(url), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().contains("no response in"), "{}", error);
+        drop(listener);
+    }
+
+    #[tokio::test]
+    async fn one_time_code_is_not_replayed() {
+        let code = "Action=GetSessionToken&SerialNumber=arn&TokenCode=123456";
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let error = client(3, Duration::from_millis(200))
+            .dispatch(with_payload(url, code), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(!error.to_string().contains("attempts"), "{}", error);
+        drop(listener);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let error = client(2, Duration::from_secs(5))
+            .dispatch(with_payload(url, code), None)
+            .await
+            .err()
+            .unwrap();
+        assert!(error.to_string().ends_with("(2 attempts)"), "{}", error);
+    }
+}
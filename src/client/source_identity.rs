@@ -0,0 +1,145 @@
+use super::diagnosis::{diagnose, StsContext};
+use super::util::*;
+use crate::error::Error::*;
+use crate::error::*;
+use crate::profile::HttpSettings;
+use rusoto_core::credential::StaticProvider;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{Region, RusotoError};
+use rusoto_sts::{AssumeRoleError, AssumeRoleRequest, Credentials};
+
+/// AssumeRole with SourceIdentity, which rusoto_sts doesn't know yet.
+/// The request is built and signed in the same way as rusoto_sts, and sent by rusoto_core.
+pub async fn assume_role(
+    provider: StaticProvider,
+    region: &Region,
+    http: &HttpSettings,
+    request: AssumeRoleRequest,
+    source_identity: &str,
+    context: &StsContext,
+) -> Result<Credentials> {
+    let mut signed = SignedRequest::new("POST", "sts", region, "/");
+    let payload = serde_urlencoded::to_string(params(&request, source_identity))
+        .map_err(|e| AwsResponseFormatError(e.to_string()))?;
+    signed.set_payload(Some(payload));
+    signed.set_content_type("application/x-www-form-urlencoded".to_owned());
+
+    let client = rusoto_core::Client::new_with(provider, http_client(http)?);
+    let mut response = client
+        .sign_and_dispatch(signed)
+        .await
+        .map_err(|e| diagnose(RusotoError::<AssumeRoleError>::from(e), context))?;
+    let response = response
+        .buffer()
+        .await
+        .map_err(|e| diagnose(RusotoError::<AssumeRoleError>::from(e), context))?;
+    if !response.status.is_success() {
+        return Err(diagnose(AssumeRoleError::from_response(response), context));
+    }
+
+    let body = String::from_utf8_lossy(&response.body);
+    Ok(Credentials {
+        access_key_id: element(&body, "AccessKeyId")?,
+        expiration: element(&body, "Expiration")?,
+        secret_access_key: element(&body, "SecretAccessKey")?,
+        session_token: element(&body, "SessionToken")?,
+    })
+}
+
+fn params(request: &AssumeRoleRequest, source_identity: &str) -> Vec<(String, String)> {
+    let mut params = vec![
+        ("Action".to_string(), "AssumeRole".to_string()),
+        ("Version".to_string(), "2011-06-15".to_string()),
+        ("RoleArn".to_string(), request.role_arn.clone()),
+        (
+            "RoleSessionName".to_string(),
+            request.role_session_name.clone(),
+        ),
+        ("SourceIdentity".to_string(), source_identity.to_owned()),
+    ];
+    let optional = vec![
+        (
+            "DurationSeconds",
+            request.duration_seconds.map(|d| d.to_string()),
+        ),
+        ("ExternalId", request.external_id.clone()),
+        ("SerialNumber", request.serial_number.clone()),
+        ("TokenCode", request.token_code.clone()),
+    ];
+    for (name, value) in optional {
+        if let Some(value) = value {
+            params.push((name.to_string(), value));
+        }
+    }
+    for (i, tag) in request.tags.iter().flatten().enumerate() {
+        params.push((format!("Tags.member.{}.Key", i + 1), tag.key.clone()));
+        params.push((format!("Tags.member.{}.Value", i + 1), tag.value.clone()));
+    }
+    for (i, key) in request.transitive_tag_keys.iter().flatten().enumerate() {
+        params.push((format!("TransitiveTagKeys.member.{}", i + 1), key.clone()));
+    }
+    params
+}
+
+/// Text of the first element with the name. Values in the response have no nested elements.
+fn element(body: &str, name: &str) -> Result<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    body.find(&open)
+        .map(|start| start + open.len())
+        .and_then(|start| {
+            body[start..]
+                .find(&close)
+                .map(|end| &body[start..start + end])
+        })
+        .map(|text| text.trim().to_owned())
+        .ok_or_else(|| AwsResponseFormatError(format!("no {} in AssumeRole response", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RESPONSE: &str = r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <SourceIdentity>me</SourceIdentity>
+    <Credentials>
+      <AccessKeyId>ASIA</AccessKeyId>
+      <SecretAccessKey>secret</SecretAccessKey>
+      <SessionToken>token</SessionToken>
+      <Expiration>2999-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+</AssumeRoleResponse>"#;
+
+    #[test]
+    fn parse_response() {
+        assert_eq!(element(RESPONSE, "AccessKeyId").unwrap(), "ASIA");
+        assert_eq!(
+            element(RESPONSE, "Expiration").unwrap(),
+            "2999-01-01T00:00:00Z"
+        );
+        assert!(element(RESPONSE, "Unknown").is_err());
+    }
+
+    #[test]
+    fn request_params() {
+        let request = AssumeRoleRequest {
+            role_arn: "arn:aws:iam::000000000000:role/admin".parse().unwrap(),
+            role_session_name: "me".to_string(),
+            duration_seconds: Some(900),
+            tags: Some(vec![rusoto_sts::Tag {
+                key: "Project".to_string(),
+                value: "ngydv".to_string(),
+            }]),
+            transitive_tag_keys: Some(vec!["Project".to_string()]),
+            ..AssumeRoleRequest::default()
+        };
+        assert_eq!(
+            serde_urlencoded::to_string(params(&request, "me")).unwrap(),
+            "Action=AssumeRole&Version=2011-06-15&RoleArn=arn%3Aaws%3Aiam%3A%3A000000000000%3Arole%2Fadmin\
+             &RoleSessionName=me&SourceIdentity=me&DurationSeconds=900\
+             &Tags.member.1.Key=Project&Tags.member.1.Value=ngydv&TransitiveTagKeys.member.1=Project"
+        );
+    }
+}
@@ -0,0 +1,435 @@
+use super::connector::{connector, Connector};
+use crate::error::Error::*;
+use crate::error::*;
+use crate::file::sso_cache::{self, format_time, SsoToken};
+use crate::profile::{Access, Credential, ProfileMap, SsoRole};
+use chrono::{Duration, TimeZone, Utc};
+use hyper::{Body, Client, Method, Request};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+const CLIENT_NAME: &str = "ngydv";
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+const REFRESH_TOKEN_GRANT: &str = "refresh_token";
+const SLOW_DOWN_SECONDS: u64 = 5;
+
+/// Endpoints of SSO OIDC and SSO portal, and the connector to them.
+/// `AWS_ENDPOINT_URL_SSO_OIDC` and `AWS_ENDPOINT_URL_SSO` override the regional endpoints.
+pub struct Endpoints {
+    oidc: String,
+    portal: String,
+    connector: Connector,
+}
+
+impl Endpoints {
+    pub fn new(region: &str, connector: Connector) -> Self {
+        let endpoint = |var: &str, default: String| {
+            std::env::var(var)
+                .unwrap_or(default)
+                .trim_end_matches('/')
+                .to_owned()
+        };
+        Self {
+            oidc: endpoint(
+                "AWS_ENDPOINT_URL_SSO_OIDC",
+                format!("https://oidc.{}.amazonaws.com", region),
+            ),
+            portal: endpoint(
+                "AWS_ENDPOINT_URL_SSO",
+                format!("https://portal.sso.{}.amazonaws.com", region),
+            ),
+            connector,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ClientRegistration {
+    client_id: String,
+    client_secret: String,
+    client_secret_expires_at: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    verification_uri_complete: Option<String>,
+    expires_in: i64,
+    interval: Option<u64>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct CreatedToken {
+    access_token: String,
+    expires_in: i64,
+    refresh_token: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OidcError {
+    error: String,
+    error_description: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RoleCredentials {
+    access_key_id: String,
+    secret_access_key: String,
+    session_token: String,
+    expiration: i64,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct GetRoleCredentialsResponse {
+    role_credentials: RoleCredentials,
+}
+
+/// Refreshes the session of the sso profile with the cached token.
+pub async fn send(profile_name: &str, profile_map: &mut ProfileMap) -> Result<()> {
+    let role = sso_role(profile_map, profile_name)?;
+    let http = &profile_map.get(profile_name)?.http;
+    let endpoints = Endpoints::new(&role.region, connector(http)?);
+    let token = match sso_cache::read_token(role.cache_key())? {
+        Some(token) if token.alive() => token,
+        Some(token) if token.refresh_token.is_some() && token.registered() => {
+            let token = refresh(&endpoints, token).await?;
+            sso_cache::write_token(role.cache_key(), &token)?;
+            token
+        }
+        _ => return Err(SsoLoginRequired(profile_name.to_owned())),
+    };
+    let credential = role_credentials(&endpoints, &role, &token.access_token).await?;
+    profile_map.get_mut(profile_name)?.credential = Some(credential);
+    Ok(())
+}
+
+/// Logs in with the device authorization flow, and gets the session of the sso profile.
+pub async fn login(profile_name: &str, profile_map: &mut ProfileMap) -> Result<()> {
+    let role = sso_role(profile_map, profile_name)?;
+    let http = &profile_map.get(profile_name)?.http;
+    let endpoints = Endpoints::new(&role.region, connector(http)?);
+    let cached = sso_cache::read_token(role.cache_key())?.filter(|token| token.registered());
+    let token = device_login(&endpoints, &role, cached).await?;
+    sso_cache::write_token(role.cache_key(), &token)?;
+    let credential = role_credentials(&endpoints, &role, &token.access_token).await?;
+    profile_map.get_mut(profile_name)?.credential = Some(credential);
+    Ok(())
+}
+
+fn sso_role(profile_map: &ProfileMap, profile_name: &str) -> Result<SsoRole> {
+    match &profile_map.get(profile_name)?.access {
+        Some(Access::Sso(role)) => Ok(role.clone()),
+        _ => Err(ProfileTypeError(profile_name.to_owned(), "SSO".to_string())),
+    }
+}
+
+async fn device_login(
+    endpoints: &Endpoints,
+    role: &SsoRole,
+    cached: Option<SsoToken>,
+) -> Result<SsoToken> {
+    let registration = match cached {
+        Some(SsoToken {
+            client_id: Some(client_id),
+            client_secret: Some(client_secret),
+            registration_expires_at: Some(expires_at),
+            ..
+        }) => (client_id, client_secret, expires_at),
+        _ => {
+            let registration: ClientRegistration = post(
+                endpoints,
+                &format!("{}/client/register", endpoints.oidc),
+                &serde_json::json!({ "clientName": CLIENT_NAME, "clientType": "public" }),
+            )
+            .await?
+            .map_err(oidc_error)?;
+            (
+                registration.client_id,
+                registration.client_secret,
+                format_time(Utc.timestamp(registration.client_secret_expires_at, 0)),
+            )
+        }
+    };
+    let (client_id, client_secret, registration_expires_at) = registration;
+
+    let authorization: DeviceAuthorization = post(
+        endpoints,
+        &format!("{}/device_authorization", endpoints.oidc),
+        &serde_json::json!({
+            "clientId": client_id,
+            "clientSecret": client_secret,
+            "startUrl": role.start_url,
+        }),
+    )
+    .await?
+    .map_err(oidc_error)?;
+    eprintln!(
+        "open {} in a browser, and confirm the code {}.",
+        authorization
+            .verification_uri_complete
+            .as_ref()
+            .unwrap_or(&authorization.verification_uri),
+        authorization.user_code
+    );
+
+    let deadline = Utc::now() + Duration::seconds(authorization.expires_in);
+    let mut interval = authorization.interval.unwrap_or(SLOW_DOWN_SECONDS);
+    let created = loop {
+        tokio::time::delay_for(std::time::Duration::from_secs(interval)).await;
+        let created: std::result::Result<CreatedToken, OidcError> = post(
+            endpoints,
+            &format!("{}/token", endpoints.oidc),
+            &serde_json::json!({
+                "clientId": client_id,
+                "clientSecret": client_secret,
+                "grantType": DEVICE_CODE_GRANT,
+                "deviceCode": authorization.device_code,
+            }),
+        )
+        .await?;
+        match created {
+            Ok(created) => break created,
+            Err(e) if e.error == "authorization_pending" => (),
+            Err(e) if e.error == "slow_down" => interval += SLOW_DOWN_SECONDS,
+            Err(e) => return Err(oidc_error(e)),
+        }
+        if Utc::now() > deadline {
+            return Err(SsoError("the code expired before confirmed.".to_string()));
+        }
+    };
+
+    Ok(SsoToken {
+        start_url: role.start_url.clone(),
+        region: role.region.clone(),
+        access_token: created.access_token,
+        expires_at: format_time(Utc::now() + Duration::seconds(created.expires_in)),
+        client_id: Some(client_id),
+        client_secret: Some(client_secret),
+        registration_expires_at: Some(registration_expires_at),
+        refresh_token: created.refresh_token,
+    })
+}
+
+async fn refresh(endpoints: &Endpoints, token: SsoToken) -> Result<SsoToken> {
+    let created: CreatedToken = post(
+        endpoints,
+        &format!("{}/token", endpoints.oidc),
+        &serde_json::json!({
+            "clientId": token.client_id,
+            "clientSecret": token.client_secret,
+            "grantType": REFRESH_TOKEN_GRANT,
+            "refreshToken": token.refresh_token,
+        }),
+    )
+    .await?
+    .map_err(oidc_error)?;
+    Ok(SsoToken {
+        access_token: created.access_token,
+        expires_at: format_time(Utc::now() + Duration::seconds(created.expires_in)),
+        refresh_token: created.refresh_token.or(token.refresh_token),
+        ..token
+    })
+}
+
+/// GetRoleCredentials of SSO portal.
+async fn role_credentials(
+    endpoints: &Endpoints,
+    role: &SsoRole,
+    access_token: &str,
+) -> Result<Credential> {
+    let query = serde_urlencoded::to_string([
+        ("account_id", role.account_id.as_str()),
+        ("role_name", role.role_name.as_str()),
+    ])
+    .map_err(|e| SsoError(e.to_string()))?;
+    let request = Request::builder()
+        .uri(format!(
+            "{}/federation/credentials?{}",
+            endpoints.portal, query
+        ))
+        .header("x-amz-sso_bearer_token", access_token)
+        .body(Body::empty())
+        .map_err(|e| SsoError(e.to_string()))?;
+    let (status, body) = send_request(endpoints, request).await?;
+    if !status.is_success() {
+        return Err(SsoError(format!("GetRoleCredentials {} {}", status, body)));
+    }
+    let credentials = serde_json::from_str::<GetRoleCredentialsResponse>(&body)
+        .map_err(|e| AwsResponseFormatError(e.to_string()))?
+        .role_credentials;
+    Ok(Credential {
+        access_key_id: credentials.access_key_id,
+        expiration: Utc.timestamp_millis(credentials.expiration),
+        secret_access_key: credentials.secret_access_key,
+        session_token: credentials.session_token,
+    })
+}
+
+/// Posts json to SSO OIDC. OAuth errors like `authorization_pending` are returned as `Err` in `Ok`.
+async fn post<T: DeserializeOwned>(
+    endpoints: &Endpoints,
+    url: &str,
+    body: &serde_json::Value,
+) -> Result<std::result::Result<T, OidcError>> {
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(url)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body.to_string()))
+        .map_err(|e| SsoError(e.to_string()))?;
+    let (status, body) = send_request(endpoints, request).await?;
+    if status.is_success() {
+        serde_json::from_str(&body)
+            .map(Ok)
+            .map_err(|e| AwsResponseFormatError(format!("{} {}", url, e)))
+    } else {
+        serde_json::from_str(&body)
+            .map(Err)
+            .map_err(|_| SsoError(format!("{} {} {}", url, status, body)))
+    }
+}
+
+async fn send_request(
+    endpoints: &Endpoints,
+    request: Request<Body>,
+) -> Result<(hyper::StatusCode, String)> {
+    let client = Client::builder().build::<_, Body>(endpoints.connector.clone());
+    let response = client
+        .request(request)
+        .await
+        .map_err(|e| SsoError(e.to_string()))?;
+    let status = response.status();
+    let body = hyper::body::to_bytes(response.into_body())
+        .await
+        .map_err(|e| SsoError(e.to_string()))?;
+    Ok((status, String::from_utf8_lossy(&body).to_string()))
+}
+
+fn oidc_error(e: OidcError) -> Error {
+    SsoError(match e.error_description {
+        Some(description) => format!("{}: {}", e.error, description),
+        None => e.error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::util::stand_in;
+    use crate::profile::HttpSettings;
+    use hyper::http::request::Parts;
+    use hyper::StatusCode;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static TOKEN_REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    fn sso(request: Parts, body: String) -> (StatusCode, String) {
+        let json = |body: serde_json::Value| (StatusCode::OK, body.to_string());
+        match request.uri.path() {
+            "/client/register" => json(serde_json::json!({
+                "clientId": "client", "clientSecret": "client-secret",
+                "clientIdIssuedAt": 0, "clientSecretExpiresAt": 32503680000i64
+            })),
+            "/device_authorization"
+                if body.contains(r#""startUrl":"https://example.awsapps.com/start""#) =>
+            {
+                json(serde_json::json!({
+                    "deviceCode": "device", "userCode": "ABCD-EFGH",
+                    "verificationUri": "https://device.sso.us-east-1.amazonaws.com/",
+                    "expiresIn": 600, "interval": 0
+                }))
+            }
+            "/token" if body.contains(DEVICE_CODE_GRANT) => {
+                // pending at the first poll, confirmed at the second.
+                if TOKEN_REQUESTS.fetch_add(1, Ordering::SeqCst) == 0 {
+                    (
+                        StatusCode::BAD_REQUEST,
+                        r#"{"error":"authorization_pending"}"#.to_string(),
+                    )
+                } else {
+                    json(serde_json::json!({
+                        "accessToken": "access", "tokenType": "Bearer", "expiresIn": 28800
+                    }))
+                }
+            }
+            "/token" => (
+                StatusCode::BAD_REQUEST,
+                r#"{"error":"invalid_grant","error_description":"expired"}"#.to_string(),
+            ),
+            "/federation/credentials" => match request.headers.get("x-amz-sso_bearer_token") {
+                Some(token) if token == "access" => json(serde_json::json!({
+                    "roleCredentials": {
+                        "accessKeyId": "ASIA", "secretAccessKey": "secret",
+                        "sessionToken": "token", "expiration": 32503680000000i64
+                    }
+                })),
+                _ => (StatusCode::UNAUTHORIZED, "{}".to_string()),
+            },
+            _ => (StatusCode::NOT_FOUND, "".to_string()),
+        }
+    }
+
+    fn endpoints(url: &str) -> Endpoints {
+        Endpoints {
+            oidc: url.to_owned(),
+            portal: url.to_owned(),
+            connector: connector(&HttpSettings::default()).unwrap(),
+        }
+    }
+
+    fn role() -> SsoRole {
+        SsoRole {
+            start_url: "https://example.awsapps.com/start".to_string(),
+            region: "us-east-1".to_string(),
+            account_id: "000000000000".to_string(),
+            role_name: "ReadOnly".to_string(),
+            session_name: Some("my-sso".to_string()),
+        }
+    }
+
+    #[tokio::test]
+    async fn device_authorization_flow() {
+        let endpoints = endpoints(&stand_in(sso));
+        let token = device_login(&endpoints, &role(), None).await.unwrap();
+        assert_eq!(token.access_token, "access");
+        assert_eq!(token.client_id.as_deref(), Some("client"));
+        assert!(token.alive());
+        assert!(token.registered());
+
+        let credential = role_credentials(&endpoints, &role(), &token.access_token)
+            .await
+            .unwrap();
+        assert_eq!(credential.access_key_id, "ASIA");
+        assert_eq!(credential.expiration, Utc.ymd(3000, 1, 1).and_hms(0, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn errors() {
+        let endpoints = endpoints(&stand_in(sso));
+        assert!(role_credentials(&endpoints, &role(), "invalid")
+            .await
+            .is_err());
+
+        let token = SsoToken {
+            start_url: role().start_url,
+            region: role().region,
+            access_token: "old".to_string(),
+            expires_at: format_time(Utc::now()),
+            client_id: Some("client".to_string()),
+            client_secret: Some("client-secret".to_string()),
+            registration_expires_at: None,
+            refresh_token: Some("refresh".to_string()),
+        };
+        match refresh(&endpoints, token).await {
+            Err(SsoError(message)) => assert_eq!(message, "invalid_grant: expired"),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+}
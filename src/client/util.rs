@@ -1,4 +1,9 @@
+use super::retry::RetryClient;
+use crate::error::Error::*;
 use crate::error::*;
+use crate::profile::credential_process::ProcessCredential;
+use crate::profile::{Access, HttpSettings, Profile};
+use rusoto_credential::{AwsCredentials, ProvideAwsCredentials};
 
 pub fn profile_provider(profile: &str) -> Result<rusoto_credential::ProfileProvider> {
     let mut provider = rusoto_credential::ProfileProvider::new()?;
@@ -6,6 +11,51 @@ pub fn profile_provider(profile: &str) -> Result<rusoto_credential::ProfileProvi
     Ok(provider)
 }
 
-pub fn http_client() -> Result<rusoto_core::HttpClient> {
-    Ok(rusoto_core::HttpClient::new()?)
+/// Long-term keys of the profile, from the credentials file or from `credential_process`.
+pub async fn long_term_credentials(profile: &Profile) -> Result<AwsCredentials> {
+    match &profile.access {
+        Some(Access::AccessKey(_)) => Ok(profile_provider(&profile.profile_name)?
+            .credentials()
+            .await?),
+        Some(Access::CredentialProcess(process)) => {
+            ProcessCredential::run(&process.command, process.timeout)
+                .await?
+                .aws_credentials()
+        }
+        _ => Err(ProfileParamNotFound(
+            profile.profile_name.clone(),
+            "ACCESS_KEY".to_string(),
+        )),
+    }
+}
+
+/// Http client with the proxy, the CA bundle, the timeouts and the retries of the profile.
+pub fn http_client(settings: &HttpSettings) -> Result<RetryClient> {
+    RetryClient::new(settings)
+}
+
+/// Starts a server answering with `handle` on a random port, and returns its url.
+/// `handle` is called with the request and its body.
+#[cfg(test)]
+pub fn stand_in(
+    handle: fn(hyper::http::request::Parts, String) -> (hyper::StatusCode, String),
+) -> String {
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Request, Response, Server};
+    use std::convert::Infallible;
+
+    let make_service = make_service_fn(move |_| async move {
+        Ok::<_, Infallible>(service_fn(move |request: Request<Body>| async move {
+            let (parts, body) = request.into_parts();
+            let body = hyper::body::to_bytes(body).await.unwrap_or_default();
+            let (status, body) = handle(parts, String::from_utf8_lossy(&body).to_string());
+            let mut response = Response::new(Body::from(body));
+            *response.status_mut() = status;
+            Ok::<_, Infallible>(response)
+        }))
+    });
+    let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+    let url = format!("http://{}", server.local_addr());
+    tokio::spawn(server);
+    url
 }
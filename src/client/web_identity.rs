@@ -0,0 +1,108 @@
+use super::diagnosis::{diagnose, StsContext};
+use super::endpoint::sts_region;
+use super::util::*;
+use crate::error::Error::*;
+use crate::error::*;
+use crate::profile::params::SessionParams;
+use crate::profile::{Access, Credential, ProfileMap, WebIdentityRole};
+use rusoto_core::credential::StaticProvider;
+use rusoto_credential::AwsCredentials;
+use rusoto_sts::{AssumeRoleWithWebIdentityRequest, Sts, StsClient};
+
+/// Assumes role with the token in the file. The request is not signed, so no credentials are needed.
+pub async fn send(profile_name: &str, profile_map: &mut ProfileMap) -> Result<()> {
+    let profile = profile_map.get(profile_name)?;
+    let role = match &profile.access {
+        Some(Access::WebIdentity(role)) => role,
+        _ => return Err(AssumeRoleSettingNotFound(profile_name.to_owned())),
+    };
+
+    let request = request(
+        profile_name,
+        role,
+        &profile.session_params,
+        read_token(role)?,
+    );
+    // empty keys make rusoto send the request without signature.
+    let anonymous = StaticProvider::from(AwsCredentials::default());
+    let region = sts_region(profile);
+    let context = StsContext {
+        operation: "AssumeRoleWithWebIdentity",
+        profile: profile_name.to_owned(),
+        role_arn: Some(role.role_arn.to_string()),
+        mfa_serial: None,
+        source: Some(format!("web identity token {}", role.token_file)),
+        region: region.name().to_owned(),
+    };
+    let response = StsClient::new_with(http_client(&profile.http)?, anonymous, region)
+        .assume_role_with_web_identity(request)
+        .await
+        .map_err(|e| diagnose(e, &context))?;
+    let rusoto_credential = response.credentials.ok_or(AwsResponseFormatError(
+        "no credential in AssumeRoleWithWebIdentity response".to_string(),
+    ))?;
+
+    profile_map.get_mut(profile_name)?.credential = Some(Credential::new(&rusoto_credential)?);
+    Ok(())
+}
+
+/// The token is read at every request, as CI runners and kubelet rotate the file.
+fn read_token(role: &WebIdentityRole) -> Result<String> {
+    std::fs::read_to_string(&role.token_file)
+        .map(|token| token.trim().to_owned())
+        .map_err(|e| WebIdentityTokenError(role.token_file.clone(), e.to_string()))
+}
+
+fn request(
+    profile_name: &str,
+    role: &WebIdentityRole,
+    params: &SessionParams,
+    token: String,
+) -> AssumeRoleWithWebIdentityRequest {
+    AssumeRoleWithWebIdentityRequest {
+        duration_seconds: params.duration_seconds,
+        role_arn: role.role_arn.to_string(),
+        role_session_name: params.role_session_name(profile_name),
+        web_identity_token: token,
+        ..AssumeRoleWithWebIdentityRequest::default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn role(token_file: &str) -> WebIdentityRole {
+        WebIdentityRole {
+            role_arn: "arn:aws:iam::000000000000:role/gha".parse().unwrap(),
+            token_file: token_file.to_owned(),
+        }
+    }
+
+    #[test]
+    fn token_file() {
+        let path = std::env::temp_dir().join(format!("ngydv-web-identity-{}", std::process::id()));
+        std::fs::write(&path, "eyJhbGciOi.token\n").unwrap();
+        let token = read_token(&role(path.to_str().unwrap()));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(token.unwrap(), "eyJhbGciOi.token");
+
+        assert!(matches!(
+            read_token(&role("/nonexistent/token")),
+            Err(WebIdentityTokenError(_, _))
+        ));
+    }
+
+    #[test]
+    fn request_params() {
+        let params = SessionParams {
+            duration_seconds: Some(900),
+            role_session_name: Some("ci-{profile}".to_string()),
+            ..SessionParams::default()
+        };
+        let request = request("gha", &role("/tmp/token"), &params, "token".to_string());
+        assert_eq!(request.role_session_name, "ci-gha");
+        assert_eq!(request.duration_seconds, Some(900));
+        assert_eq!(request.web_identity_token, "token");
+    }
+}
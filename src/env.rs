@@ -1,3 +1,5 @@
+use crate::profile::export;
+use crate::shell::Shell;
 use prettytable::*;
 use serde::Deserialize;
 
@@ -24,6 +26,10 @@ pub struct EnvVariables {
     /// If defined, this environment variable overrides the value for the profile setting region. You can override this environment variable by using the --region command line parameter.
     pub aws_default_region: Option<String>,
 
+    /// Specifies a value of maximum retry attempts the AWS CLI retry handler uses, where the initial call counts toward the value that you provide.
+    /// If defined, this environment variable overrides the value for the profile setting max_attempts.
+    pub aws_max_attempts: Option<String>,
+
     /// Specifies the pager program used for output. By default, AWS CLI version 2 returns all output through your operating system’s default pager program.
     /// To disable all use of an external paging program, set the variable to an empty string.
     /// If defined, this environment variable overrides the value for the profile setting cli_pager.
@@ -33,6 +39,10 @@ pub struct EnvVariables {
     /// If defined, this environment variable overrides the behavior of using the profile named [default] in the configuration file. You can override this environment variable by using the --profile command line parameter.
     pub aws_profile: Option<String>,
 
+    /// Specifies which retry mode AWS CLI uses. There are three retry modes available: legacy, standard and adaptive.
+    /// If defined, this environment variable overrides the value for the profile setting retry_mode.
+    pub aws_retry_mode: Option<String>,
+
     /// Specifies a name to associate with the role session. This value appears in CloudTrail logs for commands performed by the user of this profile.
     /// If defined, this environment variable overrides the value for the profile setting role_session_name. You can't specify a role session name as a command line parameter.
     pub aws_role_session_name: Option<String>,
@@ -51,14 +61,25 @@ pub struct EnvVariables {
 }
 
 pub fn env_vars() -> EnvVariables {
-    let env;
     match envy::from_env::<EnvVariables>() {
-        Ok(config) => {
-            env = config;
-        }
+        Ok(config) => config,
         Err(error) => panic!("{:#?}", error),
     }
-    env
+}
+
+/// Value of the environment variable, or `None` when it is not set or empty.
+pub fn lookup(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+/// Stands in for `lookup` in tests, with the given variables.
+#[cfg(test)]
+pub fn fake(vars: Vec<(&str, &str)>) -> impl Fn(&str) -> Option<String> + Sync {
+    let vars: std::collections::HashMap<String, String> = vars
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
 }
 
 fn create_env_list() -> Vec<(Option<String>, Option<String>, String, String)> {
@@ -100,12 +121,24 @@ fn create_env_list() -> Vec<(Option<String>, Option<String>, String, String)> {
             "aws_pager".to_string(),
             "The pager program used for output".to_string(),
         ),
+        (
+            env.aws_max_attempts.clone(),
+            Some("3".to_string()),
+            "aws_max_attempts".to_string(),
+            "The maximum attempts of a request, including the initial call".to_string(),
+        ),
         (
             env.aws_profile.clone(),
             Some("default".to_string()),
             "aws_profile".to_string(),
             "The name of the CLI profile with the credentials and options to use".to_string(),
         ),
+        (
+            env.aws_retry_mode.clone(),
+            Some("standard".to_string()),
+            "aws_retry_mode".to_string(),
+            "The retry mode, legacy, standard or adaptive".to_string(),
+        ),
         (
             env.aws_role_session_name.clone(),
             None,
@@ -156,11 +189,18 @@ pub fn list_environment_vars() {
     table.printstd();
 }
 
-pub fn clear_environment_vars() {
-    let rows = create_env_list();
-    for row in rows {
-        let (_, _, name, _) = row;
-        println!("unset {}", name.to_uppercase());
-    }
-    println!("echo clear all aws cli related environment variables.");
+pub fn clear_environment_vars(shell: Shell) {
+    let names = create_env_list()
+        .into_iter()
+        .map(|(_, _, name, _)| name.to_uppercase())
+        .collect::<Vec<String>>();
+    print!(
+        "{}",
+        export::rc(
+            shell,
+            vec![],
+            names.iter().map(|name| name.as_str()).collect(),
+            vec!["clear all aws cli related environment variables."],
+        )
+    );
 }
@@ -1,14 +1,14 @@
+use crate::client::diagnosis::{Cause, StsFailure};
+use serde_json::json;
 use thiserror::Error;
 
 pub type Result<T> = std::result::Result<T, Error>;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum Error {
-    #[error("AssumeRole request error {0:?}")]
-    AssumeRoleError(#[from] rusoto_core::RusotoError<rusoto_sts::AssumeRoleError>),
-
-    #[error("GetSessionToken request error {0:?}")]
-    GetSessionTokenError(#[from] rusoto_core::RusotoError<rusoto_sts::GetSessionTokenError>),
+    #[error("{0}")]
+    StsFailed(Box<StsFailure>),
 
     #[error("Profile {0:?} is not for crate session nor assume role")]
     ProfileNotForSignIn(String),
@@ -19,8 +19,14 @@ pub enum Error {
     #[error("Request get session token error {0:?}")]
     RusotoTlsError(#[from] rusoto_core::request::TlsError),
 
+    #[error("STS is unreachable. {0}")]
+    StsUnreachable(String),
+
+    #[error("Unable to set up HTTPS connection. {0}")]
+    HttpClientError(String),
+
     #[error("rusoto credentials error {0:?}")]
-    RusotoCredentialsError(#[from] rusoto_credential::CredentialsError),
+    RusotoCredentialsError(Box<rusoto_credential::CredentialsError>),
 
     #[error("Profile {0} is not found.")]
     ProfileNotFound(String),
@@ -34,6 +40,45 @@ pub enum Error {
     #[error("profile {0} is not for assume role.")]
     AssumeRoleSettingNotFound(String),
 
+    #[error("source_profile of the profiles makes a cycle. {0}")]
+    RoleChainCycleError(String),
+
+    #[error("Unable to get credentials of credential_source. {0}")]
+    CredentialSourceError(String),
+
+    #[error("Unable to read web identity token file {0:?}. {1}")]
+    WebIdentityTokenError(String, String),
+
+    #[error("SSO error. {0}")]
+    SsoError(String),
+
+    #[error("Profile {0} is not logged in to SSO. Log in with `ngydv login {0}`.")]
+    SsoLoginRequired(String),
+
+    #[error("Profile {0} requires mfa token. Sign in with `ngydv in {0}`.")]
+    MfaTokenRequired(String),
+
+    #[error("Mfa token {0:?} is not 6 digits.")]
+    InvalidMfaToken(String),
+
+    #[error("Mfa token for {0} was rejected. The code may be wrong, or the clock of the device may be off.")]
+    MfaTokenRejected(String),
+
+    #[error("{0} of {1} roles chained from the profile were not assumed.")]
+    DependentsFailed(usize, usize),
+
+    #[error("Unable to read mfa token from the terminal. {0}")]
+    MfaInputError(String),
+
+    #[error("Unable to get mfa token from mfa_process. {0}")]
+    MfaProcessError(String),
+
+    #[error("Invalid mfa seed. {0}")]
+    MfaSeedError(String),
+
+    #[error("Mfa seed store error. {0}")]
+    MfaSeedStoreError(String),
+
     #[error("Config file error. {0:?}")]
     ConfigFileError(String),
 
@@ -55,6 +100,209 @@ pub enum Error {
     #[error("Unable to remove credentail file at {0:?}.")]
     UnableToRemoveCredentialFileError(String),
 
+    #[error("Section [{0}] in credentials file is not written by ngydv. It is left as it is.")]
+    UnmanagedSectionError(String),
+
     #[error("Session expired at {0:?}.")]
     SessionExpiredError(String),
+
+    #[error("Unable to execute command. {0}")]
+    CommandExecutionError(String),
+
+    #[error("Unable to create credential process output. {0}")]
+    CredentialProcessError(String),
+
+    #[error("Credentials server error. {0}")]
+    ServerError(String),
+
+    #[error("Unknown shell {0:?}.")]
+    UnknownShellError(String),
+
+    #[error("Shell {0} is not supported.")]
+    UnsupportedShellError(String),
+}
+
+impl From<rusoto_credential::CredentialsError> for Error {
+    fn from(e: rusoto_credential::CredentialsError) -> Self {
+        Error::RusotoCredentialsError(Box::new(e))
+    }
+}
+
+/// Kinds of errors, which decide the exit code of the process.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorKind {
+    Other,
+    NotFound,
+    NotSignedIn,
+    Expired,
+    StsRejected,
+    StsUnreachable,
+    ConfigInvalid,
+    Io,
+}
+
+impl ErrorKind {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::NotFound => 3,
+            ErrorKind::NotSignedIn => 4,
+            ErrorKind::Expired => 5,
+            ErrorKind::StsRejected => 6,
+            ErrorKind::StsUnreachable => 7,
+            ErrorKind::ConfigInvalid => 8,
+            ErrorKind::Io => 9,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::NotFound => "not_found",
+            ErrorKind::NotSignedIn => "not_signed_in",
+            ErrorKind::Expired => "expired",
+            ErrorKind::StsRejected => "sts_rejected",
+            ErrorKind::StsUnreachable => "sts_unreachable",
+            ErrorKind::ConfigInvalid => "config_invalid",
+            ErrorKind::Io => "io",
+        }
+    }
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        use Error::*;
+        match self {
+            StsFailed(failure) if failure.cause == Cause::SourceSessionExpired => {
+                ErrorKind::Expired
+            }
+            StsFailed(_)
+            | MfaTokenRejected(_)
+            | AwsResponseFormatError(_)
+            | DependentsFailed(..) => ErrorKind::StsRejected,
+            StsUnreachable(_) | HttpClientError(_) | RusotoTlsError(_) => ErrorKind::StsUnreachable,
+            ProfileNotFound(_)
+            | ProfileTypeError(..)
+            | ProfileParamNotFound(..)
+            | ProfileNotForSignIn(_)
+            | AssumeRoleSettingNotFound(_) => ErrorKind::NotFound,
+            ProfileNotSignedIn(_) | SsoLoginRequired(_) | MfaTokenRequired(_) => {
+                ErrorKind::NotSignedIn
+            }
+            SessionExpiredError(_) => ErrorKind::Expired,
+            ConfigFileError(_)
+            | NgydvConfigError(_)
+            | RoleChainCycleError(_)
+            | UnknownShellError(_)
+            | UnsupportedShellError(_)
+            | InvalidMfaToken(_)
+            | MfaSeedError(_) => ErrorKind::ConfigInvalid,
+            CredentialFileError(_)
+            | UserHomeNotFoundError
+            | UnableToWriteCredentialFileError(_)
+            | UnableToRemoveCredentialFileError(_)
+            | UnmanagedSectionError(_)
+            | WebIdentityTokenError(..)
+            | MfaInputError(_)
+            | MfaSeedStoreError(_)
+            | CommandExecutionError(_) => ErrorKind::Io,
+            RusotoCredentialsError(_)
+            | CredentialSourceError(_)
+            | SsoError(_)
+            | MfaProcessError(_)
+            | CredentialProcessError(_)
+            | ServerError(_) => ErrorKind::Other,
+        }
+    }
+
+    /// The error object printed with `--error-format json`. `raw` is the STS response, only with `--verbose`.
+    pub fn to_json(&self, verbose: bool) -> String {
+        let kind = self.kind();
+        let debug = format!("{:?}", self);
+        let mut error = json!({
+            "kind": kind.name(),
+            "exit_code": kind.exit_code(),
+            "variant": debug.split('(').next().unwrap_or_default(),
+            "message": self.to_string(),
+        });
+        if let Error::StsFailed(failure) = self {
+            error["message"] = json!(failure.message);
+            error["hint"] = json!(failure.hint);
+            if verbose {
+                error["raw"] = json!(failure.raw);
+            }
+        }
+        json!({ "error": error }).to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::diagnosis::StsFailure;
+
+    fn sts_failure(cause: Cause) -> Error {
+        Error::StsFailed(Box::new(StsFailure {
+            cause,
+            message: "AssumeRole for profile admin failed.".to_string(),
+            hint: Some("check the trust policy.".to_string()),
+            raw: "403 Forbidden".to_string(),
+        }))
+    }
+
+    #[test]
+    fn exit_codes() {
+        assert_eq!(
+            Error::ProfileNotFound("a".to_string()).kind().exit_code(),
+            3
+        );
+        assert_eq!(
+            Error::MfaTokenRequired("a".to_string()).kind().exit_code(),
+            4
+        );
+        assert_eq!(
+            Error::SessionExpiredError("a".to_string())
+                .kind()
+                .exit_code(),
+            5
+        );
+        assert_eq!(
+            sts_failure(Cause::SourceSessionExpired).kind(),
+            ErrorKind::Expired
+        );
+        assert_eq!(sts_failure(Cause::AccessDenied).kind().exit_code(), 6);
+        assert_eq!(
+            Error::ConfigFileError("a".to_string()).kind().exit_code(),
+            8
+        );
+        assert_eq!(Error::UserHomeNotFoundError.kind().exit_code(), 9);
+    }
+
+    #[test]
+    fn json() {
+        let value: serde_json::Value =
+            serde_json::from_str(&Error::ProfileNotFound("dev".to_string()).to_json(false))
+                .unwrap();
+        assert_eq!(
+            value,
+            json!({"error": {
+                "kind": "not_found",
+                "exit_code": 3,
+                "variant": "ProfileNotFound",
+                "message": "Profile dev is not found.",
+            }})
+        );
+
+        let value: serde_json::Value =
+            serde_json::from_str(&sts_failure(Cause::AccessDenied).to_json(true)).unwrap();
+        assert_eq!(
+            value["error"]["message"],
+            "AssumeRole for profile admin failed."
+        );
+        assert_eq!(value["error"]["hint"], "check the trust policy.");
+        assert_eq!(value["error"]["raw"], "403 Forbidden");
+        let value: serde_json::Value =
+            serde_json::from_str(&sts_failure(Cause::AccessDenied).to_json(false)).unwrap();
+        assert!(value["error"].get("raw").is_none());
+    }
 }
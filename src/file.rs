@@ -1,8 +1,12 @@
 pub mod aws_setting;
 pub mod credentials;
+pub mod mfa_history;
+pub mod mfa_seeds;
+pub mod shared_credentials;
+pub mod sso_cache;
 use crate::error::Error::*;
 use crate::error::*;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn aws_config_dir() -> Result<PathBuf> {
     let home_dir = dirs::home_dir().ok_or(UserHomeNotFoundError)?;
@@ -21,6 +25,77 @@ pub fn ngydv_config_file() -> Result<PathBuf> {
     Ok(aws_config_dir()?.join("ngydv"))
 }
 
+pub fn sso_cache_dir() -> Result<PathBuf> {
+    Ok(aws_config_dir()?.join("sso/cache"))
+}
+
+pub fn mfa_history_path() -> Result<PathBuf> {
+    Ok(aws_config_dir()?.join("ngydv_mfa_history.yaml"))
+}
+
+pub fn mfa_seeds_path() -> Result<PathBuf> {
+    Ok(aws_config_dir()?.join("ngydv_mfa_seeds.json"))
+}
+
 pub fn credentials_path() -> Result<PathBuf> {
     Ok(aws_config_dir()?.join("ngydv_credentials.yaml"))
 }
+
+pub fn daemon_socket_path() -> Result<PathBuf> {
+    Ok(aws_config_dir()?.join("ngydv_daemon.sock"))
+}
+
+/// Writes a temporary file next to the path and renames it over the path, so that the file is
+/// never left partially written. An existing file keeps its mode, and a new one gets `mode`.
+pub fn replace_file(path: &Path, contents: &[u8], mode: u32) -> std::io::Result<()> {
+    use std::io::Write;
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.{}.tmp", name, std::process::id()));
+    std::fs::remove_file(&temp).ok();
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(mode);
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    let written = options.open(&temp).and_then(|mut file| {
+        if let Ok(metadata) = std::fs::metadata(path) {
+            file.set_permissions(metadata.permissions())?;
+        }
+        file.write_all(contents)?;
+        file.sync_all()
+    });
+    let replaced = written.and_then(|_| std::fs::rename(&temp, path));
+    if replaced.is_err() {
+        std::fs::remove_file(&temp).ok();
+    }
+    replaced
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn replace_file_keeps_mode() {
+        let dir = std::env::temp_dir().join(format!("ngydv-file-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("credentials");
+        let mode = |path: &Path| std::fs::metadata(path).unwrap().permissions().mode() & 0o777;
+
+        replace_file(&path, b"new", 0o600).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        assert_eq!(mode(&path), 0o600);
+
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        replace_file(&path, b"replaced", 0o600).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "replaced");
+        assert_eq!(mode(&path), 0o640);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
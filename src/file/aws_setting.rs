@@ -1,31 +1,58 @@
+use crate::client::retry::RETRY_MODES;
 use crate::error::Error::*;
 use crate::error::*;
 use crate::file;
 use crate::file::credentials::restore_credentials;
+use crate::file::shared_credentials::managed_sections;
+use crate::profile::params::SessionParams;
 use crate::profile::*;
 use ini::ini::{Ini, Properties};
 use std::collections::HashMap;
 
+const DEFAULT_CREDENTIAL_PROCESS_TIMEOUT: u64 = 60;
+
 pub type PropertyMap = HashMap<String, (usize, HashMap<String, String>)>;
 
+const SSO_SESSION_PREFIX: &str = "sso-session ";
+
+/// Profile made from `AWS_ROLE_ARN` and `AWS_WEB_IDENTITY_TOKEN_FILE`, unless the config has the same name.
+pub const WEB_IDENTITY_PROFILE: &str = "web-identity";
+
 pub fn read_aws_config() -> Result<ProfileMap> {
     let mut props = HashMap::new();
 
     let conf = Ini::load_from_file(file::aws_config_file()?)
         .map_err(|e| ConfigFileError(format!("{:?}", e)))?;
     read_config(&conf, &mut props)?;
+    apply_sso_sessions(&read_sso_sessions(&conf), &mut props)?;
 
-    let cred = Ini::load_from_file(file::aws_credential_file()?)
+    let text = std::fs::read_to_string(file::aws_credential_file()?)
         .map_err(|e| CredentialFileError(format!("{:?}", e)))?;
-    read_credentials(&cred, &mut props)?;
+    let cred = Ini::load_from_str(&text).map_err(|e| CredentialFileError(format!("{:?}", e)))?;
+    read_credentials(&cred, &managed_sections(&text), &mut props)?;
 
     if let Ok(ngydv) = Ini::load_from_file(file::ngydv_config_file()?) {
         read_ngydv(&ngydv, &mut props)?;
     }
 
+    if let (Ok(role_arn), Ok(token_file)) = (
+        std::env::var("AWS_ROLE_ARN"),
+        std::env::var("AWS_WEB_IDENTITY_TOKEN_FILE"),
+    ) {
+        add_web_identity_profile(&role_arn, &token_file, &mut props);
+    }
+
     let mut profiles = ProfileMap::new();
     for (name, prop) in props.iter() {
-        profiles.insert(name, create_profile(name, prop));
+        match create_profile(name, prop) {
+            Ok(profile) => profiles.insert(name, profile),
+            // a bad setting fails only the commands using the profile.
+            Err(ConfigFileError(message)) => profiles.insert_invalid(name, message),
+            Err(e) => return Err(e),
+        }
+    }
+    for (name, message) in profiles.invalid_profiles() {
+        eprintln!("profile '{}' can't be used. {}", name, message);
     }
 
     restore_credentials(&mut profiles)?;
@@ -33,11 +60,12 @@ pub fn read_aws_config() -> Result<ProfileMap> {
     Ok(profiles)
 }
 
-fn create_profile(name: &str, prop: &(usize, HashMap<String, String>)) -> Profile {
+fn create_profile(name: &str, prop: &(usize, HashMap<String, String>)) -> Result<Profile> {
     let (order, prop) = prop;
     let mut profile = Profile {
         profile_name: name.to_owned(),
         order: *order,
+        session_params: SessionParams::from_props(name, prop)?,
         ..Profile::default()
     };
 
@@ -45,6 +73,42 @@ fn create_profile(name: &str, prop: &(usize, HashMap<String, String>)) -> Profil
         profile.region = Some(region.to_string());
     }
 
+    let mfa_serial = mfa_serial(name, prop)?;
+
+    if let Some(session_profile) = prop.get("session_profile") {
+        profile.session_profile = Some(session_profile.to_string());
+    }
+
+    if let Some(mfa_process) = prop.get("mfa_process") {
+        profile.mfa_process = Some(mfa_process.to_string());
+    }
+
+    profile.http.ca_bundle = prop.get("ca_bundle").map(|s| s.to_string());
+    profile.http.retry_mode = match prop.get("retry_mode") {
+        Some(mode) if RETRY_MODES.contains(&mode.as_str()) => Some(mode.to_string()),
+        Some(mode) => {
+            return Err(ConfigFileError(format!(
+                "retry_mode {:?} of profile {} is not legacy, standard nor adaptive.",
+                mode, name
+            )))
+        }
+        None => None,
+    };
+    profile.http.max_attempts = positive_number(name, prop, "max_attempts")?;
+    profile.http.connect_timeout = positive_number(name, prop, "connect_timeout")?;
+    profile.http.read_timeout = positive_number(name, prop, "read_timeout")?;
+    profile.sts_endpoint.endpoint_url = prop.get("endpoint_url").map(|s| s.to_string());
+    profile.sts_endpoint.regional = match prop.get("sts_regional_endpoints") {
+        Some(value) if value == "regional" || value == "legacy" => Some(value.to_string()),
+        Some(value) => {
+            return Err(ConfigFileError(format!(
+                "sts_regional_endpoints {:?} is not regional nor legacy.",
+                value
+            )))
+        }
+        None => None,
+    };
+
     if let (Some(access_key_id), Some(secret_access_key)) = (
         prop.get("aws_access_key_id"),
         prop.get("aws_secret_access_key"),
@@ -52,28 +116,123 @@ fn create_profile(name: &str, prop: &(usize, HashMap<String, String>)) -> Profil
         profile.access = Some(Access::AccessKey(AccessKey {
             access_key_id: access_key_id.to_string(),
             secret_access_key: secret_access_key.to_string(),
-            mfa_serial: prop.get("mfa_serial").map(|s| s.to_string()),
+            mfa_serial: mfa_serial.clone(),
+        }));
+    }
+
+    if let (None, Some(command)) = (&profile.access, prop.get("credential_process")) {
+        let timeout = match prop.get("credential_process_timeout") {
+            Some(seconds) => seconds.parse().map_err(|_| {
+                ConfigFileError(format!(
+                    "credential_process_timeout {:?} of profile {} is not a number.",
+                    seconds, name
+                ))
+            })?,
+            None => DEFAULT_CREDENTIAL_PROCESS_TIMEOUT,
+        };
+        profile.access = Some(Access::CredentialProcess(ProcessKeys {
+            command: command.to_string(),
+            mfa_serial: mfa_serial.clone(),
+            timeout: std::time::Duration::from_secs(timeout),
+        }));
+    }
+
+    if let (Some(start_url), Some(region), Some(account_id), Some(role_name)) = (
+        prop.get("sso_start_url"),
+        prop.get("sso_region"),
+        prop.get("sso_account_id"),
+        prop.get("sso_role_name"),
+    ) {
+        profile.access = Some(Access::Sso(SsoRole {
+            start_url: start_url.to_string(),
+            region: region.to_string(),
+            account_id: account_id.to_string(),
+            role_name: role_name.to_string(),
+            session_name: prop.get("sso_session").map(|s| s.to_string()),
         }));
     }
 
-    if let (Some(role_arn), Some(mfa_serial), Some(source_profile)) = (
-        prop.get("role_arn"),
-        prop.get("mfa_serial"),
+    let role_arn = match prop.get("role_arn") {
+        Some(role_arn) => Some(Arn::parse_iam(
+            role_arn,
+            &format!("role_arn of profile {}", name),
+            "role",
+        )?),
+        None => None,
+    };
+    match (
+        role_arn,
         prop.get("source_profile"),
+        prop.get("credential_source"),
     ) {
-        profile.access = Some(Access::AssumeRole(AssumedRole {
-            role_arn: role_arn.to_string(),
-            mfa_serial: mfa_serial.to_string(),
-            source_profile: source_profile.to_string(),
-        }))
+        (Some(role_arn), None, None) => {
+            if let Some(token_file) = prop.get("web_identity_token_file") {
+                profile.access = Some(Access::WebIdentity(WebIdentityRole {
+                    role_arn,
+                    token_file: token_file.to_string(),
+                }))
+            }
+        }
+        (Some(role_arn), Some(source_profile), None) => {
+            profile.access = Some(Access::AssumeRole(AssumedRole {
+                role_arn,
+                mfa_serial: mfa_serial.clone(),
+                source_profile: source_profile.to_string(),
+            }))
+        }
+        (Some(role_arn), None, Some(credential_source)) => {
+            profile.access = Some(Access::CredentialSource(SourcedRole {
+                role_arn,
+                mfa_serial: mfa_serial.clone(),
+                credential_source: credential_source.parse()?,
+            }))
+        }
+        (Some(_), Some(_), Some(_)) => {
+            return Err(ConfigFileError(format!(
+                "profile {} has both source_profile and credential_source.",
+                name
+            )))
+        }
+        _ => (),
     }
 
-    profile
+    Ok(profile)
+}
+
+/// `mfa_serial` is the ARN of a virtual device, or the serial number of a hardware device.
+fn mfa_serial(name: &str, prop: &HashMap<String, String>) -> Result<Option<String>> {
+    match prop.get("mfa_serial") {
+        Some(serial) if serial.starts_with("arn:") => {
+            Arn::parse_iam(serial, &format!("mfa_serial of profile {}", name), "mfa")?;
+            Ok(Some(serial.to_owned()))
+        }
+        Some(serial) => Ok(Some(serial.to_owned())),
+        None => Ok(None),
+    }
+}
+
+fn positive_number<T>(name: &str, prop: &HashMap<String, String>, key: &str) -> Result<Option<T>>
+where
+    T: std::str::FromStr + Default + PartialOrd,
+{
+    match prop.get(key) {
+        Some(value) => match value.parse::<T>() {
+            Ok(number) if number > T::default() => Ok(Some(number)),
+            _ => Err(ConfigFileError(format!(
+                "{} {:?} of profile {} is not a positive number.",
+                key, value, name
+            ))),
+        },
+        None => Ok(None),
+    }
 }
 
 fn read_config(conf: &Ini, props: &mut PropertyMap) -> Result<()> {
     for (section_key, properties) in conf {
         let section = section_key.ok_or(ConfigFileError("section name not found".to_string()))?;
+        if section.starts_with(SSO_SESSION_PREFIX) {
+            continue;
+        }
         let profile_name = profile_name(section).ok_or(ConfigFileError(format!(
             "section header line {} is empty.",
             section
@@ -84,10 +243,58 @@ fn read_config(conf: &Ini, props: &mut PropertyMap) -> Result<()> {
     Ok(())
 }
 
-fn read_credentials(cred: &Ini, props: &mut PropertyMap) -> Result<()> {
+fn read_sso_sessions(conf: &Ini) -> HashMap<String, HashMap<String, String>> {
+    conf.iter()
+        .filter_map(|(section_key, properties)| {
+            section_key
+                .and_then(|section| section.strip_prefix(SSO_SESSION_PREFIX))
+                .map(|name| (name.trim().to_owned(), properties_to_vec(properties)))
+        })
+        .collect()
+}
+
+/// Copies `sso_start_url` and `sso_region` of `[sso-session name]` into the profiles with `sso_session = name`.
+fn apply_sso_sessions(
+    sessions: &HashMap<String, HashMap<String, String>>,
+    props: &mut PropertyMap,
+) -> Result<()> {
+    for (profile_name, (_, prop)) in props.iter_mut() {
+        if let Some(session_name) = prop.get("sso_session") {
+            let session = sessions.get(session_name).ok_or(ConfigFileError(format!(
+                "sso-session {} of profile {} is not found.",
+                session_name, profile_name
+            )))?;
+            for key in &["sso_start_url", "sso_region"] {
+                if let Some(value) = session.get(*key) {
+                    prop.insert(key.to_string(), value.to_owned());
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn add_web_identity_profile(role_arn: &str, token_file: &str, props: &mut PropertyMap) {
+    if !props.contains_key(WEB_IDENTITY_PROFILE) {
+        let prop = vec![
+            ("role_arn".to_string(), role_arn.to_owned()),
+            ("web_identity_token_file".to_string(), token_file.to_owned()),
+        ];
+        props.insert(
+            WEB_IDENTITY_PROFILE.to_string(),
+            (props.len(), prop.into_iter().collect()),
+        );
+    }
+}
+
+/// `managed` sections have sessions written by ngydv, which are not keys of the profiles.
+fn read_credentials(cred: &Ini, managed: &[String], props: &mut PropertyMap) -> Result<()> {
     for (section_key, prop) in cred {
         let section =
             section_key.ok_or(CredentialFileError("section name not found".to_string()))?;
+        if managed.iter().any(|name| name == section) {
+            continue;
+        }
         let profile_name = profile_name(section).ok_or(CredentialFileError(format!(
             "section header line {} is empty.",
             section
@@ -183,7 +390,7 @@ aws_secret_access_key = {}
             let ini = Ini::load_from_str(&profile_text()).unwrap();
             read_config(&ini, &mut props).unwrap();
             let ini = Ini::load_from_str(&credential_text()).unwrap();
-            read_credentials(&ini, &mut props).unwrap();
+            read_credentials(&ini, &[], &mut props).unwrap();
 
             let expected = Profile {
                 order: 0,
@@ -195,12 +402,274 @@ aws_secret_access_key = {}
                     mfa_serial: None,
                 })),
                 credential: None,
+                ..Profile::default()
             };
             assert_eq!(
-                create_profile("default", &props.get("default").unwrap()),
+                create_profile("default", props.get("default").unwrap()).unwrap(),
                 expected
             );
         }
+
+        #[test]
+        fn managed_sections_are_not_keys() {
+            let text = format!(
+                "{}\n[session]\n# managed by ngydv. removed by `ngydv clear session`.\naws_access_key_id = ASIA\naws_secret_access_key = s\naws_session_token = t\nexpiration = 2020-01-01T00:00:00Z\n",
+                credential_text()
+            );
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(&text).unwrap();
+            read_credentials(&ini, &managed_sections(&text), &mut props).unwrap();
+            assert!(props.contains_key("default"));
+            assert!(!props.contains_key("session"));
+        }
+
+        #[test]
+        fn create_profile_with_sso_session() {
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(
+                r#"
+[profile dev]
+sso_session = my-sso
+sso_account_id = 000000000000
+sso_role_name = ReadOnly
+[sso-session my-sso]
+sso_start_url = https://example.awsapps.com/start
+sso_region = us-east-1
+[profile legacy]
+sso_start_url = https://legacy.awsapps.com/start
+sso_region = eu-west-1
+sso_account_id = 111111111111
+sso_role_name = Admin
+[profile missing]
+sso_session = none
+"#,
+            )
+            .unwrap();
+            read_config(&ini, &mut props).unwrap();
+            assert!(!props.contains_key("sso-session my-sso"));
+            assert!(apply_sso_sessions(&read_sso_sessions(&ini), &mut props).is_err());
+
+            props.remove("missing");
+            apply_sso_sessions(&read_sso_sessions(&ini), &mut props).unwrap();
+            let profile = create_profile("dev", props.get("dev").unwrap()).unwrap();
+            assert_eq!(
+                profile.access,
+                Some(Access::Sso(SsoRole {
+                    start_url: "https://example.awsapps.com/start".to_string(),
+                    region: "us-east-1".to_string(),
+                    account_id: "000000000000".to_string(),
+                    role_name: "ReadOnly".to_string(),
+                    session_name: Some("my-sso".to_string()),
+                }))
+            );
+            let profile = create_profile("legacy", props.get("legacy").unwrap()).unwrap();
+            match profile.access {
+                Some(Access::Sso(role)) => {
+                    assert_eq!(role.cache_key(), "https://legacy.awsapps.com/start")
+                }
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+
+        #[test]
+        fn create_profile_with_credential_process() {
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(
+                r#"
+[profile vault]
+credential_process = vault-aws --profile base
+mfa_serial = arn:aws:iam::000000000000:mfa/me
+credential_process_timeout = 10
+[profile keys]
+aws_access_key_id = AKIA
+aws_secret_access_key = secret
+credential_process = ignored
+"#,
+            )
+            .unwrap();
+            read_config(&ini, &mut props).unwrap();
+
+            let profile = create_profile("vault", props.get("vault").unwrap()).unwrap();
+            assert_eq!(profile.profile_type(), ProfileType::SessionWithMFA);
+            assert_eq!(
+                profile.access,
+                Some(Access::CredentialProcess(ProcessKeys {
+                    command: "vault-aws --profile base".to_string(),
+                    mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+                    timeout: std::time::Duration::from_secs(10),
+                }))
+            );
+            let profile = create_profile("keys", props.get("keys").unwrap()).unwrap();
+            assert!(matches!(profile.access, Some(Access::AccessKey(_))));
+        }
+
+        #[test]
+        fn create_profile_with_mfa_process() {
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(&credential_text()).unwrap();
+            read_credentials(&ini, &[], &mut props).unwrap();
+            let ini = Ini::load_from_str(
+                r#"
+[default]
+mfa_serial = arn:aws:iam::000000000000:mfa/me
+mfa_process = ykman oath accounts code -s aws
+"#,
+            )
+            .unwrap();
+            read_ngydv(&ini, &mut props).unwrap();
+
+            let profile = create_profile("default", props.get("default").unwrap()).unwrap();
+            assert_eq!(profile.profile_type(), ProfileType::SessionWithMFA);
+            assert_eq!(
+                profile.mfa_process,
+                Some("ykman oath accounts code -s aws".to_string())
+            );
+        }
+
+        #[test]
+        fn create_profile_with_invalid_arn() {
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(
+                r#"
+[profile short]
+role_arn = arn:aws:iam::1234:role/admin
+source_profile = default
+[profile mfa]
+role_arn = arn:aws:iam::123456789012:mfa/me
+source_profile = default
+[profile serial]
+role_arn = arn:aws-us-gov:iam::123456789012:role/admin
+source_profile = default
+mfa_serial = arn:aws:iam::123456789012:user/me
+[profile hardware]
+role_arn = arn:aws-us-gov:iam::123456789012:role/admin
+source_profile = default
+mfa_serial = GAHT12345678
+"#,
+            )
+            .unwrap();
+            read_config(&ini, &mut props).unwrap();
+
+            for name in &["short", "mfa", "serial"] {
+                assert!(matches!(
+                    create_profile(name, props.get(*name).unwrap()),
+                    Err(ConfigFileError(message)) if message.contains(name)
+                ));
+            }
+            let profile = create_profile("hardware", props.get("hardware").unwrap()).unwrap();
+            assert_eq!(profile.partition(), Some(Partition::AwsUsGov));
+            assert_eq!(profile.mfa_serial(), Some("GAHT12345678"));
+        }
+
+        #[test]
+        fn create_profile_with_sts_endpoint() {
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(
+                r#"
+[profile local]
+region = ap-east-1
+endpoint_url = http://localhost:4566
+sts_regional_endpoints = legacy
+ca_bundle = /etc/ssl/corp.pem
+retry_mode = legacy
+max_attempts = 4
+read_timeout = 5
+[profile typo]
+sts_regional_endpoints = regionl
+[profile timeout]
+connect_timeout = 0
+"#,
+            )
+            .unwrap();
+            read_config(&ini, &mut props).unwrap();
+
+            let profile = create_profile("local", props.get("local").unwrap()).unwrap();
+            assert_eq!(
+                profile.sts_endpoint,
+                StsEndpoint {
+                    override_url: None,
+                    endpoint_url: Some("http://localhost:4566".to_string()),
+                    regional: Some("legacy".to_string()),
+                }
+            );
+            assert_eq!(
+                profile.http.ca_bundle,
+                Some("/etc/ssl/corp.pem".to_string())
+            );
+            assert_eq!(profile.http.retry_mode, Some("legacy".to_string()));
+            assert_eq!(profile.http.max_attempts, Some(4));
+            assert_eq!(profile.http.read_timeout, Some(5));
+            assert_eq!(profile.http.connect_timeout, None);
+            assert!(create_profile("typo", props.get("typo").unwrap()).is_err());
+            assert!(create_profile("timeout", props.get("timeout").unwrap()).is_err());
+        }
+
+        #[test]
+        fn create_profile_with_web_identity() {
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(
+                r#"
+[profile gha]
+role_arn = arn:aws:iam::000000000000:role/gha
+web_identity_token_file = /var/run/token
+"#,
+            )
+            .unwrap();
+            read_config(&ini, &mut props).unwrap();
+            add_web_identity_profile(
+                "arn:aws:iam::000000000000:role/env",
+                "/tmp/token",
+                &mut props,
+            );
+
+            let profile = create_profile("gha", props.get("gha").unwrap()).unwrap();
+            assert_eq!(profile.profile_type(), ProfileType::WebIdentity);
+            let profile = create_profile(
+                WEB_IDENTITY_PROFILE,
+                props.get(WEB_IDENTITY_PROFILE).unwrap(),
+            )
+            .unwrap();
+            assert_eq!(
+                profile.access,
+                Some(Access::WebIdentity(WebIdentityRole {
+                    role_arn: "arn:aws:iam::000000000000:role/env".parse().unwrap(),
+                    token_file: "/tmp/token".to_string(),
+                }))
+            );
+        }
+
+        #[test]
+        fn create_profile_with_credential_source() {
+            let mut props = HashMap::new();
+            let ini = Ini::load_from_str(
+                r#"
+[profile ci]
+role_arn = arn:aws:iam::000000000000:role/deploy
+credential_source = Ec2InstanceMetadata
+[profile both]
+role_arn = arn:aws:iam::000000000000:role/deploy
+credential_source = Environment
+source_profile = default
+[profile unknown]
+role_arn = arn:aws:iam::000000000000:role/deploy
+credential_source = Lambda
+"#,
+            )
+            .unwrap();
+            read_config(&ini, &mut props).unwrap();
+
+            let profile = create_profile("ci", props.get("ci").unwrap()).unwrap();
+            assert_eq!(
+                profile.access,
+                Some(Access::CredentialSource(SourcedRole {
+                    role_arn: "arn:aws:iam::000000000000:role/deploy".parse().unwrap(),
+                    mfa_serial: None,
+                    credential_source: CredentialSource::Ec2InstanceMetadata,
+                }))
+            );
+            assert!(create_profile("both", props.get("both").unwrap()).is_err());
+            assert!(create_profile("unknown", props.get("unknown").unwrap()).is_err());
+        }
     }
 
     mod read_credentials {
@@ -211,11 +680,11 @@ aws_secret_access_key = {}
             let mut props = HashMap::new();
 
             let ini = Ini::load_from_str(&credential_text()).unwrap();
-            assert!(read_credentials(&ini, &mut props).is_ok());
+            assert!(read_credentials(&ini, &[], &mut props).is_ok());
             let mut expected = HashMap::new();
             add_prop(
                 &mut expected,
-                &"default",
+                "default",
                 vec![
                     ("aws_access_key_id", KEY_ID),
                     ("aws_secret_access_key", SECRET_KEY),
@@ -232,12 +701,12 @@ aws_secret_access_key = {}
             assert!(read_config(&ini, &mut props).is_ok());
 
             let ini = Ini::load_from_str(&credential_text()).unwrap();
-            assert!(read_credentials(&ini, &mut props).is_ok());
+            assert!(read_credentials(&ini, &[], &mut props).is_ok());
 
             let mut expected = HashMap::new();
             add_prop(
                 &mut expected,
-                &"default",
+                "default",
                 vec![
                     ("aws_access_key_id", KEY_ID),
                     ("aws_secret_access_key", SECRET_KEY),
@@ -251,7 +720,7 @@ aws_secret_access_key = {}
         fn read_credential_error(str: &str) {
             let mut props = HashMap::new();
             let ini = Ini::load_from_str(str).unwrap();
-            assert!(read_credentials(&ini, &mut props).is_err());
+            assert!(read_credentials(&ini, &[], &mut props).is_err());
         }
 
         #[test]
@@ -5,6 +5,7 @@ use crate::profile;
 use crate::profile::*;
 use std::collections::HashMap;
 use std::fs::File;
+use std::path::Path;
 
 pub fn store_credentials(profile_map: &ProfileMap) -> Result<()> {
     let mut save_data: HashMap<String, profile::Credential> = HashMap::new();
@@ -16,31 +17,47 @@ pub fn store_credentials(profile_map: &ProfileMap) -> Result<()> {
             }
         }
     }
+    write_saved_credentials(&save_data)
+}
+
+/// Replaces the file at once, so that readers never see a partial file.
+pub fn write_saved_credentials(save_data: &HashMap<String, profile::Credential>) -> Result<()> {
     let path = credentials_path()?;
-    let path_str = path.as_path().display().to_string();
-    let writer = File::create(path).or(Err(UnableToWriteCredentialFileError(path_str.clone())))?;
-    serde_yaml::to_writer(writer, &save_data)
-        .or(Err(UnableToWriteCredentialFileError(path_str.clone())))?;
-    Ok(())
+    let error = || UnableToWriteCredentialFileError(path.display().to_string());
+    let text = serde_yaml::to_string(save_data).map_err(|_| error())?;
+    replace_file(&path, text.as_bytes(), 0o600).map_err(|_| error())
 }
 
 pub fn restore_credentials(profile_map: &mut ProfileMap) -> Result<()> {
-    if let Ok(file) = File::open(credentials_path()?) {
-        match serde_yaml::from_reader::<File, HashMap<String, profile::Credential>>(file) {
-            Err(_) => (),
-            Ok(save_data) => {
-                for (profile_name, profile) in profile_map.iter_mut() {
-                    match save_data.get(profile_name) {
-                        None => (),
-                        Some(cred) => profile.credential = Some(cred.clone()),
-                    }
-                }
-            }
+    let save_data = read_saved_credentials()?;
+    for (profile_name, profile) in profile_map.iter_mut() {
+        match save_data.get(profile_name) {
+            None => (),
+            Some(cred) => profile.credential = Some(cred.clone()),
         }
     }
     Ok(())
 }
 
+pub fn read_saved_credentials() -> Result<HashMap<String, profile::Credential>> {
+    read_sessions(&credentials_path()?)
+}
+
+/// Sessions in the file, or none without the file. A broken file is an error rather than no
+/// sessions, so that the daemon doesn't write its sessions over the others.
+fn read_sessions(path: &Path) -> Result<HashMap<String, profile::Credential>> {
+    match File::open(path) {
+        Ok(file) => serde_yaml::from_reader(file).map_err(|e| {
+            CredentialFileError(format!(
+                "{} is broken, remove it with `ngydv clear session`. {}",
+                path.display(),
+                e
+            ))
+        }),
+        Err(_) => Ok(HashMap::new()),
+    }
+}
+
 pub fn delete_credentials() -> Result<()> {
     if credentials_path()?.exists() {
         std::fs::remove_file(credentials_path()?).or(Err(UnableToRemoveCredentialFileError(
@@ -49,3 +66,26 @@ pub fn delete_credentials() -> Result<()> {
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn broken_file() {
+        let dir = std::env::temp_dir().join(format!("ngydv-credentials-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("ngydv_credentials.yaml");
+        assert!(read_sessions(&path).unwrap().is_empty());
+
+        std::fs::write(&path, "{}").unwrap();
+        assert!(read_sessions(&path).unwrap().is_empty());
+
+        std::fs::write(&path, "base:\n  access_key_id: [").unwrap();
+        match read_sessions(&path) {
+            Err(CredentialFileError(message)) => assert!(message.contains("ngydv clear session")),
+            other => panic!("{:?}", other),
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,38 @@
+use crate::error::Error::*;
+use crate::error::*;
+use crate::file::*;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+
+/// The last one time code accepted for a mfa device, as STS rejects the same code used again.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct UsedToken {
+    pub token: String,
+    pub used_at: DateTime<Utc>,
+}
+
+pub fn read_used_tokens() -> HashMap<String, UsedToken> {
+    match mfa_history_path().map(File::open) {
+        Ok(Ok(file)) => serde_yaml::from_reader(file).unwrap_or_default(),
+        _ => HashMap::new(),
+    }
+}
+
+pub fn record_used_token(mfa_serial: &str, token: &str) -> Result<()> {
+    let mut used_tokens = read_used_tokens();
+    used_tokens.insert(
+        mfa_serial.to_owned(),
+        UsedToken {
+            token: token.to_owned(),
+            used_at: Utc::now(),
+        },
+    );
+    let path = mfa_history_path()?;
+    let path_str = path.as_path().display().to_string();
+    let writer = File::create(path).or(Err(UnableToWriteCredentialFileError(path_str.clone())))?;
+    serde_yaml::to_writer(writer, &used_tokens)
+        .or(Err(UnableToWriteCredentialFileError(path_str)))?;
+    Ok(())
+}
@@ -0,0 +1,134 @@
+use crate::error::Error::*;
+use crate::error::*;
+use crate::file::*;
+use crate::profile::totp::TotpSeed;
+use aes_gcm::aead::generic_array::GenericArray;
+use aes_gcm::aead::{Aead, NewAead};
+use aes_gcm::Aes256Gcm;
+use data_encoding::BASE64;
+use hmac::Hmac;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+
+const ITERATIONS: u32 = 200_000;
+
+/// Seeds of mfa devices keyed by `mfa_serial`, encrypted with AES-256-GCM.
+/// The key is derived from the passphrase with PBKDF2-HMAC-SHA256.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct SealedSeeds {
+    version: u8,
+    iterations: u32,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+}
+
+pub fn seed_store_exists() -> bool {
+    mfa_seeds_path().map(|path| path.exists()).unwrap_or(false)
+}
+
+pub fn read_seeds(passphrase: &str) -> Result<HashMap<String, TotpSeed>> {
+    let path = mfa_seeds_path()?;
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let error = |e: String| MfaSeedStoreError(format!("{}: {}", path.display(), e));
+    let text = std::fs::read_to_string(&path).map_err(|e| error(e.to_string()))?;
+    let sealed = serde_json::from_str(&text).map_err(|e| error(e.to_string()))?;
+    open(&sealed, passphrase)
+}
+
+pub fn write_seeds(seeds: &HashMap<String, TotpSeed>, passphrase: &str) -> Result<()> {
+    let path = mfa_seeds_path()?;
+    let error = |e: String| MfaSeedStoreError(format!("{}: {}", path.display(), e));
+    let text = serde_json::to_string(&seal(seeds, passphrase, ITERATIONS)?)
+        .map_err(|e| error(e.to_string()))?;
+    replace_file(&path, text.as_bytes(), 0o600).map_err(|e| error(e.to_string()))
+}
+
+fn seal(
+    seeds: &HashMap<String, TotpSeed>,
+    passphrase: &str,
+    iterations: u32,
+) -> Result<SealedSeeds> {
+    let mut salt = [0u8; 16];
+    let mut nonce = [0u8; 12];
+    rand::thread_rng().fill_bytes(&mut salt);
+    rand::thread_rng().fill_bytes(&mut nonce);
+    let plaintext = serde_json::to_vec(seeds).map_err(|e| MfaSeedStoreError(e.to_string()))?;
+    let ciphertext = cipher(passphrase, &salt, iterations)
+        .encrypt(GenericArray::from_slice(&nonce), plaintext.as_ref())
+        .map_err(|_| MfaSeedStoreError("unable to encrypt seeds.".to_string()))?;
+    Ok(SealedSeeds {
+        version: 1,
+        iterations,
+        salt: BASE64.encode(&salt),
+        nonce: BASE64.encode(&nonce),
+        ciphertext: BASE64.encode(&ciphertext),
+    })
+}
+
+fn open(sealed: &SealedSeeds, passphrase: &str) -> Result<HashMap<String, TotpSeed>> {
+    if sealed.version != 1 {
+        return Err(MfaSeedStoreError(format!(
+            "version {} is not supported.",
+            sealed.version
+        )));
+    }
+    let decode = |value: &str| {
+        BASE64
+            .decode(value.as_bytes())
+            .map_err(|e| MfaSeedStoreError(e.to_string()))
+    };
+    if sealed.iterations == 0 {
+        return Err(MfaSeedStoreError(
+            "iterations is 0, the file is broken.".to_string(),
+        ));
+    }
+    let nonce = decode(&sealed.nonce)?;
+    if nonce.len() != 12 {
+        return Err(MfaSeedStoreError("nonce is broken.".to_string()));
+    }
+    let plaintext = cipher(passphrase, &decode(&sealed.salt)?, sealed.iterations)
+        .decrypt(
+            GenericArray::from_slice(&nonce),
+            decode(&sealed.ciphertext)?.as_ref(),
+        )
+        .map_err(|_| MfaSeedStoreError("wrong passphrase, or the file is broken.".to_string()))?;
+    serde_json::from_slice(&plaintext).map_err(|e| MfaSeedStoreError(e.to_string()))
+}
+
+fn cipher(passphrase: &str, salt: &[u8], iterations: u32) -> Aes256Gcm {
+    let mut key = [0u8; 32];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), salt, iterations, &mut key);
+    Aes256Gcm::new(GenericArray::from_slice(&key))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        let mut seeds = HashMap::new();
+        seeds.insert(
+            "arn:aws:iam::000000000000:mfa/user".to_string(),
+            TotpSeed::parse("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap(),
+        );
+        let sealed = seal(&seeds, "passphrase", 10).unwrap();
+        assert!(!sealed.ciphertext.contains("GEZDGNBV"));
+        assert_eq!(open(&sealed, "passphrase").unwrap(), seeds);
+        assert!(matches!(open(&sealed, "wrong"), Err(MfaSeedStoreError(_))));
+
+        let broken = SealedSeeds {
+            iterations: 0,
+            ..sealed
+        };
+        assert!(matches!(
+            open(&broken, "passphrase"),
+            Err(MfaSeedStoreError(_))
+        ));
+    }
+}
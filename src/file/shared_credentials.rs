@@ -0,0 +1,263 @@
+use crate::error::Error::*;
+use crate::error::*;
+use crate::file::*;
+use crate::profile::{Credential, Profile};
+use chrono::SecondsFormat;
+
+/// First line of the sections written by ngydv. Only sections with this line are rewritten or removed.
+const MANAGED_MARKER: &str = "# managed by ngydv. removed by `ngydv clear session`.";
+
+/// Writes the session of the profile into sections of ~/.aws/credentials, for tools reading only the file.
+/// The section is `section` if given, `session_profile` of the profile otherwise.
+pub fn write_sessions(profile: &Profile, section: Option<String>, default: bool) -> Result<()> {
+    let sections = target_sections(profile, section, default);
+    if sections.is_empty() {
+        return Ok(());
+    }
+
+    let credential = profile
+        .credential
+        .as_ref()
+        .ok_or_else(|| ProfileNotSignedIn(profile.profile_name.clone()))?;
+
+    let mut text = read()?;
+    for section in sections.iter() {
+        text = replace_section(&text, section, Some(&section_body(credential)))?;
+        eprintln!(
+            "wrote session of profile '{}' to [{}] in credentials file.",
+            profile.profile_name, section
+        );
+    }
+    write(&text)
+}
+
+/// Fails if any of the sections `write_sessions` writes is not written by ngydv,
+/// so that nothing is exported before the error.
+pub fn check_sessions(profile: &Profile, section: &Option<String>, default: bool) -> Result<()> {
+    let sections = target_sections(profile, section.clone(), default);
+    if sections.is_empty() {
+        return Ok(());
+    }
+    let text = read()?;
+    let lines: Vec<&str> = text.lines().collect();
+    for (name, start, end) in self::sections(&lines) {
+        if sections.contains(&name) && !is_managed(&lines, start, end) {
+            return Err(UnmanagedSectionError(name));
+        }
+    }
+    Ok(())
+}
+
+fn target_sections(profile: &Profile, section: Option<String>, default: bool) -> Vec<String> {
+    let mut sections: Vec<String> = section
+        .or_else(|| profile.session_profile.clone())
+        .into_iter()
+        .collect();
+    if default {
+        sections.push("default".to_string());
+    }
+    sections
+}
+
+/// Removes all sections written by ngydv and returns their names.
+pub fn remove_managed_sections() -> Result<Vec<String>> {
+    let mut text = read()?;
+    let sections = managed_sections(&text);
+    for section in sections.iter() {
+        text = replace_section(&text, section, None)?;
+    }
+    if !sections.is_empty() {
+        write(&text)?;
+    }
+    Ok(sections)
+}
+
+fn read() -> Result<String> {
+    let path = aws_credential_file()?;
+    if !path.exists() {
+        return Ok(String::new());
+    }
+    std::fs::read_to_string(&path).map_err(|e| CredentialFileError(e.to_string()))
+}
+
+/// Replaces the file at once, not to lose the keys in it. A new file is readable only by the user.
+fn write(text: &str) -> Result<()> {
+    let path = aws_credential_file()?;
+    replace_file(&path, text.as_bytes(), 0o600)
+        .map_err(|_| UnableToWriteCredentialFileError(path.display().to_string()))
+}
+
+fn section_body(credential: &Credential) -> String {
+    format!(
+        "{}\naws_access_key_id = {}\naws_secret_access_key = {}\naws_session_token = {}\nexpiration = {}\n",
+        MANAGED_MARKER,
+        credential.access_key_id,
+        credential.secret_access_key,
+        credential.session_token,
+        credential
+            .expiration
+            .to_rfc3339_opts(SecondsFormat::Secs, true)
+    )
+}
+
+fn section_name(line: &str) -> Option<&str> {
+    let line = line.trim();
+    if line.starts_with('[') && line.ends_with(']') {
+        Some(line[1..line.len() - 1].trim())
+    } else {
+        None
+    }
+}
+
+/// Line ranges of the sections, from the header line to the last key line.
+/// Blank lines and comments before the next header are left to the next section.
+fn sections(lines: &[&str]) -> Vec<(String, usize, usize)> {
+    let headers: Vec<(usize, &str)> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| section_name(line).map(|name| (i, name)))
+        .collect();
+    headers
+        .iter()
+        .enumerate()
+        .map(|(n, (start, name))| {
+            let mut end = headers.get(n + 1).map_or(lines.len(), |(next, _)| *next);
+            while end > start + 1 && is_blank_or_comment(lines[end - 1]) {
+                end -= 1;
+            }
+            (name.to_string(), *start, end)
+        })
+        .collect()
+}
+
+fn is_blank_or_comment(line: &str) -> bool {
+    let line = line.trim();
+    line.is_empty() || (line.starts_with('#') || line.starts_with(';')) && line != MANAGED_MARKER
+}
+
+fn is_managed(lines: &[&str], start: usize, end: usize) -> bool {
+    lines[start + 1..end]
+        .iter()
+        .any(|line| line.trim() == MANAGED_MARKER)
+}
+
+/// Sections written by ngydv, which have sessions but not keys of profiles.
+pub fn managed_sections(text: &str) -> Vec<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    sections(&lines)
+        .into_iter()
+        .filter(|(_, start, end)| is_managed(&lines, *start, *end))
+        .map(|(name, _, _)| name)
+        .collect()
+}
+
+/// Replaces the section with `body`, or removes it if `body` is None.
+/// Sections not written by ngydv are never touched, so the keys of users are never lost.
+fn replace_section(text: &str, name: &str, body: Option<&str>) -> Result<String> {
+    let lines: Vec<&str> = text.lines().collect();
+    let new_section = body.map(|body| format!("[{}]\n{}", name, body));
+
+    match sections(&lines).into_iter().find(|(n, _, _)| n == name) {
+        Some((_, start, end)) => {
+            if !is_managed(&lines, start, end) {
+                return Err(UnmanagedSectionError(name.to_owned()));
+            }
+            let mut ret = match new_section {
+                Some(section) => join(&lines[..start]) + &section,
+                // the blank line separating the section is removed with it.
+                None if start > 0 && lines[start - 1].trim().is_empty() => {
+                    join(&lines[..start - 1])
+                }
+                None => join(&lines[..start]),
+            };
+            ret.push_str(&join(&lines[end..]));
+            Ok(ret)
+        }
+        None => {
+            let mut ret = text.to_owned();
+            if let Some(section) = new_section {
+                if !ret.is_empty() && !ret.ends_with('\n') {
+                    ret.push('\n');
+                }
+                if !ret.trim().is_empty() {
+                    ret.push('\n');
+                }
+                ret.push_str(&section);
+            }
+            Ok(ret)
+        }
+    }
+}
+
+fn join(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const USER_FILE: &str = r#"# my keys
+[default]
+aws_access_key_id = AKIA
+aws_secret_access_key = secret ; keep this
+
+[other]
+aws_access_key_id = AKIB
+aws_secret_access_key = secret2
+"#;
+
+    fn body(key: &str) -> String {
+        format!("{}\naws_access_key_id = {}\n", MANAGED_MARKER, key)
+    }
+
+    #[test]
+    fn append_and_replace() {
+        let text = replace_section(USER_FILE, "session", Some(&body("ASIA1"))).unwrap();
+        assert_eq!(text, format!("{}\n[session]\n{}", USER_FILE, body("ASIA1")));
+
+        let text = replace_section(&text, "session", Some(&body("ASIA2"))).unwrap();
+        assert_eq!(text, format!("{}\n[session]\n{}", USER_FILE, body("ASIA2")));
+        assert_eq!(managed_sections(&text), vec!["session".to_string()]);
+    }
+
+    #[test]
+    fn replace_in_the_middle() {
+        let text = format!("[session]\n{}\n{}", body("ASIA1"), USER_FILE);
+        let replaced = replace_section(&text, "session", Some(&body("ASIA2"))).unwrap();
+        assert_eq!(
+            replaced,
+            format!("[session]\n{}\n{}", body("ASIA2"), USER_FILE)
+        );
+    }
+
+    #[test]
+    fn remove_keeps_other_sections_and_comments() {
+        let text = replace_section(USER_FILE, "session", Some(&body("ASIA1"))).unwrap();
+        let removed = replace_section(&text, "session", None).unwrap();
+        assert_eq!(removed, USER_FILE);
+    }
+
+    #[test]
+    fn target_sections_of_profile() {
+        let profile = Profile {
+            session_profile: Some("session".to_string()),
+            ..Profile::default()
+        };
+        assert_eq!(
+            target_sections(&profile, None, true),
+            vec!["session", "default"]
+        );
+        assert_eq!(
+            target_sections(&profile, Some("other".to_string()), false),
+            vec!["other"]
+        );
+        assert!(target_sections(&Profile::default(), None, false).is_empty());
+    }
+
+    #[test]
+    fn unmanaged_section_is_not_overwritten() {
+        assert!(replace_section(USER_FILE, "default", Some(&body("ASIA1"))).is_err());
+        assert!(replace_section(USER_FILE, "other", None).is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use crate::error::Error::*;
+use crate::error::*;
+use crate::file::*;
+use chrono::{DateTime, NaiveDateTime, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+/// Token of IAM Identity Center in ~/.aws/sso/cache, in the same format as AWS CLI.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SsoToken {
+    pub start_url: String,
+    pub region: String,
+    pub access_token: String,
+    pub expires_at: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub registration_expires_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub refresh_token: Option<String>,
+}
+
+impl SsoToken {
+    pub fn alive(&self) -> bool {
+        parse_time(&self.expires_at).is_some_and(|at| at > Utc::now())
+    }
+
+    /// Whether the client registration in the token can be used for another login.
+    pub fn registered(&self) -> bool {
+        self.client_id.is_some()
+            && self.client_secret.is_some()
+            && self
+                .registration_expires_at
+                .as_deref()
+                .and_then(parse_time)
+                .is_some_and(|at| at > Utc::now())
+    }
+}
+
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// AWS CLI v1 wrote `2019-11-14T04:05:45UTC`, v2 writes RFC 3339.
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .map(|time| time.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%SUTC")
+                .ok()
+                .map(|time| DateTime::from_utc(time, Utc))
+        })
+}
+
+/// File name of the cache is SHA-1 of the sso-session name, or of the start url.
+fn cache_file(key: &str) -> Result<PathBuf> {
+    Ok(sso_cache_dir()?.join(format!("{}.json", sha1_hex(key))))
+}
+
+pub fn read_token(key: &str) -> Result<Option<SsoToken>> {
+    let path = cache_file(key)?;
+    if !path.exists() {
+        return Ok(None);
+    }
+    let text = std::fs::read_to_string(&path)
+        .map_err(|e| SsoError(format!("{}: {}", path.display(), e)))?;
+    // an unreadable cache is the same as no cache, a new login overwrites it.
+    Ok(serde_json::from_str(&text).ok())
+}
+
+pub fn write_token(key: &str, token: &SsoToken) -> Result<()> {
+    let path = cache_file(key)?;
+    let error = |e: String| SsoError(format!("{}: {}", path.display(), e));
+    std::fs::create_dir_all(sso_cache_dir()?).map_err(|e| error(e.to_string()))?;
+    let text = serde_json::to_string(token).map_err(|e| error(e.to_string()))?;
+    replace_file(&path, text.as_bytes(), 0o600).map_err(|e| error(e.to_string()))
+}
+
+fn sha1_hex(key: &str) -> String {
+    Sha1::digest(key.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn cache_file_name() {
+        assert_eq!(sha1_hex("abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn cli_format() {
+        let token: SsoToken = serde_json::from_str(
+            r#"{"startUrl":"https://example.awsapps.com/start","region":"us-east-1",
+                "accessToken":"token","expiresAt":"2999-01-01T00:00:00UTC"}"#,
+        )
+        .unwrap();
+        assert!(token.alive());
+        assert!(!token.registered());
+
+        let token = SsoToken {
+            expires_at: format_time(Utc::now() - Duration::minutes(1)),
+            client_id: Some("id".to_string()),
+            client_secret: Some("secret".to_string()),
+            registration_expires_at: Some(format_time(Utc::now() + Duration::days(1))),
+            ..token
+        };
+        assert!(!token.alive());
+        assert!(token.registered());
+        assert!(serde_json::to_string(&token)
+            .unwrap()
+            .contains(r#""clientSecret":"secret","registrationExpiresAt":"#));
+    }
+}
@@ -4,60 +4,221 @@ mod error;
 mod file;
 mod opts;
 mod profile;
-use client::{assume_role, create_session};
+mod server;
+mod shell;
+use chrono::Duration;
+use client::sso;
 use env::clear_environment_vars;
 use env::list_environment_vars;
-use error::Error::*;
 use error::Result;
 use file::aws_setting::read_aws_config;
 use file::credentials::{delete_credentials, store_credentials};
+use file::shared_credentials::{check_sessions, remove_managed_sections, write_sessions};
+use profile::credential_process::ProcessCredential;
+use profile::exec;
+use profile::mfa;
+use profile::prompt::print_prompt;
 use profile::show::show_current_profile;
+use profile::warm;
 use profile::Profile;
+use shell::{init, Shell};
 use structopt::StructOpt;
 
 #[tokio::main]
 async fn main() {
-    match execute(opts::Opts::from_args()).await {
-        Ok(_) => (),
-        Err(err) => eprintln!("{}", err),
+    let opts = opts::Opts::from_args();
+    let verbose = opts.verbose;
+    let json = opts.error_format == "json";
+    if let Err(err) = execute(opts).await {
+        if json {
+            eprintln!("{}", err.to_json(verbose));
+        } else {
+            eprintln!("{}", err);
+            if let (true, error::Error::StsFailed(failure)) = (verbose, &err) {
+                eprintln!("raw response:\n{}", failure.raw);
+            }
+        }
+        std::process::exit(err.kind().exit_code());
     }
 }
 
 async fn execute(opts: opts::Opts) -> Result<()> {
+    use opts::{ClearCommand, MfaCommand, SubCommand};
+
+    // these run from shell rc files and prompts, or clear a broken credentials file,
+    // so they don't read aws config files.
+    match opts.sub_command {
+        SubCommand::Prompt { shell, no_color } => {
+            return print_prompt(shell.unwrap_or_else(Shell::detect), !no_color)
+        }
+        SubCommand::Init { shell, prompt } => {
+            print!("{}", init::script(shell, prompt)?);
+            return Ok(());
+        }
+        SubCommand::Clear { command } => {
+            match command {
+                ClearCommand::Session => {
+                    delete_credentials()?;
+                    println!("credentials file deleted.");
+                    for section in remove_managed_sections()? {
+                        println!("[{}] removed from ~/.aws/credentials.", section);
+                    }
+                }
+                ClearCommand::Env { shell } => {
+                    clear_environment_vars(shell.unwrap_or_else(Shell::detect))
+                }
+            }
+            return Ok(());
+        }
+        _ => (),
+    }
+
     let mut profile_map = read_aws_config()?;
+    if let Some(url) = &opts.endpoint_url {
+        profile_map.override_endpoint_url(url);
+    }
+    if let Some(path) = &opts.ca_bundle {
+        profile_map.override_ca_bundle(path);
+    }
 
-    use opts::{ClearCommand, SubCommand};
     match opts.sub_command {
-        SubCommand::Profile => Profile::print_table(&profile_map),
-        SubCommand::Export { profile } => {
-            profile_map.print_export(&profile).await?;
-            store_credentials(&mut profile_map)?;
+        SubCommand::Profile => Profile::print_table(&profile_map, server::daemon::status()),
+        SubCommand::Export {
+            profile,
+            shell,
+            write_profile,
+            write_default,
+        } => {
+            check_sessions(profile_map.get(&profile)?, &write_profile, write_default)?;
+            profile_map.resolve(&profile).await?;
+            store_credentials(&profile_map)?;
+            write_sessions(profile_map.get(&profile)?, write_profile, write_default)?;
+            profile_map
+                .print_export(&profile, shell.unwrap_or_else(Shell::detect))
+                .await?;
         }
         SubCommand::In {
             profile: profile_name,
             token,
+            params,
+            shell,
+            write_profile,
+            write_default,
+            with_dependents,
         } => {
             let profile = profile_map.get_mut(&profile_name)?;
-            use crate::profile::ProfileType::*;
-            match profile.profile_type() {
-                AssumeRole(_) => {
-                    assume_role::send(&profile_name, &mut profile_map, Some(token.to_owned()))
-                        .await?
+            profile.session_params.merge(params);
+            check_sessions(profile, &write_profile, write_default)?;
+            mfa::sign_in(&mut profile_map, &profile_name, token).await?;
+            if with_dependents {
+                // the failed roles are listed on stderr, and the export is still printed
+                // for the shell wrapper to evaluate.
+                match warm::dependents(&mut profile_map, &profile_name).await {
+                    Ok(()) | Err(error::Error::DependentsFailed(..)) => (),
+                    Err(e) => {
+                        // the session signed in with mfa is kept.
+                        store_credentials(&profile_map)?;
+                        return Err(e);
+                    }
                 }
-                SessionWithMFA => create_session::send(profile, &token).await?,
-                _ => return Err(ProfileNotForSignIn(profile_name.to_owned())),
             }
-            profile_map.print_export(&profile_name).await?;
-            store_credentials(&mut profile_map)?;
+            store_credentials(&profile_map)?;
+            write_sessions(
+                profile_map.get(&profile_name)?,
+                write_profile,
+                write_default,
+            )?;
+            profile_map
+                .print_export(&profile_name, shell.unwrap_or_else(Shell::detect))
+                .await?;
+        }
+        SubCommand::Warm { profile } => {
+            let warmed = warm::dependents(&mut profile_map, &profile).await;
+            store_credentials(&profile_map)?;
+            warmed?;
+        }
+        SubCommand::Login {
+            profile: profile_name,
+            shell,
+            write_profile,
+            write_default,
+        } => {
+            check_sessions(
+                profile_map.get(&profile_name)?,
+                &write_profile,
+                write_default,
+            )?;
+            let root = profile_map.source_chain(&profile_name)?.remove(0);
+            sso::login(&root, &mut profile_map).await?;
+            profile_map.resolve(&profile_name).await?;
+            store_credentials(&profile_map)?;
+            write_sessions(
+                profile_map.get(&profile_name)?,
+                write_profile,
+                write_default,
+            )?;
+            profile_map
+                .print_export(&profile_name, shell.unwrap_or_else(Shell::detect))
+                .await?;
+        }
+        SubCommand::Exec { profile, command } => {
+            profile_map.resolve(&profile).await?;
+            store_credentials(&profile_map)?;
+            let code = exec::run(profile_map.get(&profile)?, &command)?;
+            std::process::exit(code);
         }
+        SubCommand::Serve {
+            profile,
+            port,
+            refresh_before,
+            shell,
+        } => {
+            server::ecs::serve(
+                profile_map,
+                &profile,
+                port,
+                Duration::minutes(refresh_before),
+                shell.unwrap_or_else(Shell::detect),
+            )
+            .await?
+        }
+        SubCommand::Daemon {
+            profiles,
+            refresh_before,
+        } => server::daemon::run(profile_map, profiles, Duration::minutes(refresh_before)).await?,
+        SubCommand::Imds {
+            profile,
+            address,
+            port,
+            refresh_before,
+            shell,
+        } => {
+            server::imds::serve(
+                profile_map,
+                &profile,
+                address,
+                port,
+                Duration::minutes(refresh_before),
+                shell.unwrap_or_else(Shell::detect),
+            )
+            .await?
+        }
+        SubCommand::CredentialProcess { profile } => {
+            let credential = ProcessCredential::new(profile_map.resolve(&profile).await?).await?;
+            store_credentials(&profile_map)?;
+            println!("{}", credential.to_json()?);
+        }
+        SubCommand::Prompt { .. } | SubCommand::Init { .. } | SubCommand::Clear { .. } => (),
         SubCommand::Env => list_environment_vars(),
         SubCommand::Show => show_current_profile(&profile_map),
-        SubCommand::Clear { command } => match command {
-            ClearCommand::Session => {
-                delete_credentials()?;
-                println!("credentials file deleted.");
-            }
-            ClearCommand::Env => clear_environment_vars(),
+        SubCommand::Mfa { command } => match command {
+            MfaCommand::Add {
+                mfa_serial,
+                seed,
+                no_check,
+            } => mfa::add_seed(&mfa_serial, seed, !no_check)?,
+            MfaCommand::Remove { mfa_serial } => mfa::remove_seed(&mfa_serial)?,
+            MfaCommand::List => mfa::list_seeds()?,
         },
     }
 
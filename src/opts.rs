@@ -1,8 +1,25 @@
+use crate::profile::params::SessionParams;
+use crate::server::imds::local_address;
+use crate::shell::Shell;
+use std::net::IpAddr;
+use structopt::clap::AppSettings;
 use structopt::StructOpt;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "ngydv")]
 pub struct Opts {
+    /// sends STS requests to this url, instead of the endpoint of the profile.
+    #[structopt(long, global = true)]
+    pub endpoint_url: Option<String>,
+    /// trusts the certificates in this PEM file, in addition to the system ones.
+    #[structopt(long, global = true)]
+    pub ca_bundle: Option<String>,
+    /// prints the raw response of failed STS requests.
+    #[structopt(long, global = true)]
+    pub verbose: bool,
+    /// format of errors written to stderr.
+    #[structopt(long, global = true, default_value = "text", possible_values = &["text", "json"])]
+    pub error_format: String,
     #[structopt(subcommand)]
     pub sub_command: SubCommand,
 }
@@ -26,8 +43,48 @@ pub enum SubCommand {
     In {
         /// profile name.
         profile: String,
-        /// mfa-device token. 6 digits.
-        token: String,
+        /// mfa-device token. 6 digits. read from the terminal without echo if omitted.
+        token: Option<String>,
+        #[structopt(flatten)]
+        params: SessionParams,
+        /// shell dialect of the printed script. detected from $SHELL if omitted.
+        #[structopt(
+            long,
+            env = "NGYDV_SHELL",
+            possible_values = &Shell::variants(),
+            case_insensitive = true
+        )]
+        shell: Option<Shell>,
+        /// writes the session into this section of ~/.aws/credentials. `session_profile` in ~/.aws/ngydv if omitted.
+        #[structopt(long)]
+        write_profile: Option<String>,
+        /// writes the session into [default] section of ~/.aws/credentials too.
+        #[structopt(long)]
+        write_default: bool,
+        /// assumes the roles chained from the profile too, concurrently.
+        #[structopt(long)]
+        with_dependents: bool,
+    },
+
+    /// Logs in to IAM Identity Center with device authorization, and gets session of the sso profile.
+    #[structopt(name = "login")]
+    Login {
+        /// sso profile name, or role profile chained from it.
+        profile: String,
+        /// shell dialect of the printed script. detected from $SHELL if omitted.
+        #[structopt(
+            long,
+            env = "NGYDV_SHELL",
+            possible_values = &Shell::variants(),
+            case_insensitive = true
+        )]
+        shell: Option<Shell>,
+        /// writes the session into this section of ~/.aws/credentials. `session_profile` in ~/.aws/ngydv if omitted.
+        #[structopt(long)]
+        write_profile: Option<String>,
+        /// writes the session into [default] section of ~/.aws/credentials too.
+        #[structopt(long)]
+        write_default: bool,
     },
 
     /// Prints shell script to export environment variables for created session.
@@ -35,6 +92,137 @@ pub enum SubCommand {
     Export {
         /// profile name.
         profile: String,
+        /// shell dialect of the printed script. detected from $SHELL if omitted.
+        #[structopt(
+            long,
+            env = "NGYDV_SHELL",
+            possible_values = &Shell::variants(),
+            case_insensitive = true
+        )]
+        shell: Option<Shell>,
+        /// writes the session into this section of ~/.aws/credentials. `session_profile` in ~/.aws/ngydv if omitted.
+        #[structopt(long)]
+        write_profile: Option<String>,
+        /// writes the session into [default] section of ~/.aws/credentials too.
+        #[structopt(long)]
+        write_default: bool,
+    },
+
+    /// Runs a command with environment variables for the session, without changing current shell.
+    #[structopt(name = "exec", setting = AppSettings::TrailingVarArg)]
+    Exec {
+        /// profile name.
+        profile: String,
+        /// command and arguments to run. e.g. `ngydv exec profile -- aws s3 ls`
+        #[structopt(required = true)]
+        command: Vec<String>,
+    },
+
+    /// Serves credentials on localhost with the protocol of ECS container credentials provider.
+    /// Role sessions are re-assumed from the source session before they expire.
+    #[structopt(name = "serve")]
+    Serve {
+        /// profile name.
+        profile: String,
+        /// port to listen on 127.0.0.1. chosen by OS if omitted.
+        #[structopt(long, default_value = "0")]
+        port: u16,
+        /// minutes before expiration to re-assume role.
+        #[structopt(long, default_value = "15")]
+        refresh_before: i64,
+        /// shell dialect of the printed variables. detected from $SHELL if omitted.
+        #[structopt(
+            long,
+            env = "NGYDV_SHELL",
+            possible_values = &Shell::variants(),
+            case_insensitive = true
+        )]
+        shell: Option<Shell>,
+    },
+
+    /// Assumes the roles chained from the signed-in profile concurrently, and prints the results.
+    #[structopt(name = "warm")]
+    Warm {
+        /// profile name, which the roles are chained from.
+        profile: String,
+    },
+
+    /// Keeps role sessions warm, re-assuming them from the source session before they expire.
+    /// The status is served on ~/.aws/ngydv_daemon.sock.
+    #[structopt(name = "daemon")]
+    Daemon {
+        /// role profile names.
+        #[structopt(required = true)]
+        profiles: Vec<String>,
+        /// minutes before expiration to re-assume role.
+        #[structopt(long, default_value = "15")]
+        refresh_before: i64,
+    },
+
+    /// Serves credentials and region with the protocol of EC2 instance metadata service v2.
+    #[structopt(name = "imds")]
+    Imds {
+        /// profile name.
+        profile: String,
+        /// loopback or link-local address to listen on. 169.254.169.254 needs to be assigned to loopback interface to use it.
+        #[structopt(long, default_value = "127.0.0.1", parse(try_from_str = local_address))]
+        address: IpAddr,
+        /// port to listen on. chosen by OS if omitted.
+        #[structopt(long, default_value = "0")]
+        port: u16,
+        /// minutes before expiration to re-assume role.
+        #[structopt(long, default_value = "15")]
+        refresh_before: i64,
+        /// shell dialect of the printed variables. detected from $SHELL if omitted.
+        #[structopt(
+            long,
+            env = "NGYDV_SHELL",
+            possible_values = &Shell::variants(),
+            case_insensitive = true
+        )]
+        shell: Option<Shell>,
+    },
+
+    /// Prints credentials in JSON for `credential_process` setting of AWS CLI and SDKs.
+    #[structopt(name = "credential-process")]
+    CredentialProcess {
+        /// profile name.
+        profile: String,
+    },
+
+    /// Prints active profile and remaining session lifetime for the prompt.
+    #[structopt(name = "prompt")]
+    Prompt {
+        /// shell dialect of the prompt, to mark colour codes as zero width.
+        #[structopt(
+            long,
+            env = "NGYDV_SHELL",
+            possible_values = &Shell::variants(),
+            case_insensitive = true
+        )]
+        shell: Option<Shell>,
+        /// prints without colour codes.
+        #[structopt(long)]
+        no_color: bool,
+    },
+
+    /// Prints shell functions and completions to evaluate from rc file.
+    /// e.g. `eval "$(ngydv init bash)"` in ~/.bashrc, `ngydv init fish | source` in config.fish
+    #[structopt(name = "init")]
+    Init {
+        /// shell to integrate.
+        #[structopt(possible_values = &["bash", "zsh", "fish"])]
+        shell: Shell,
+        /// adds active profile to the prompt.
+        #[structopt(long)]
+        prompt: bool,
+    },
+
+    /// Manages seeds of virtual mfa devices, to compute the token of `in` without the device.
+    #[structopt(name = "mfa")]
+    Mfa {
+        #[structopt(subcommand)]
+        command: MfaCommand,
     },
 
     /// Clears session or environment variables for AWS CLI.
@@ -53,5 +241,40 @@ pub enum ClearCommand {
 
     /// Clears environment variables related AWS CLI.
     #[structopt(name = "env")]
-    Env,
+    Env {
+        /// shell dialect of the printed script. detected from $SHELL if omitted.
+        #[structopt(
+            long,
+            env = "NGYDV_SHELL",
+            possible_values = &Shell::variants(),
+            case_insensitive = true
+        )]
+        shell: Option<Shell>,
+    },
+}
+
+#[derive(StructOpt, Debug)]
+pub enum MfaCommand {
+    /// Stores the TOTP seed of the mfa device, encrypted with a passphrase.
+    #[structopt(name = "add")]
+    Add {
+        /// mfa_serial of the device.
+        mfa_serial: String,
+        /// base32 seed or `otpauth://totp/` uri. read from the terminal without echo if omitted.
+        seed: Option<String>,
+        /// stores the seed without checking it with the current code of the device.
+        #[structopt(long)]
+        no_check: bool,
+    },
+
+    /// Removes the stored seed of the mfa device.
+    #[structopt(name = "remove")]
+    Remove {
+        /// mfa_serial of the device.
+        mfa_serial: String,
+    },
+
+    /// Lists mfa_serial of the stored seeds.
+    #[structopt(name = "list")]
+    List,
 }
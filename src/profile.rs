@@ -1,13 +1,25 @@
+pub mod arn;
 mod collection;
-mod export;
+pub mod credential_process;
+pub mod exec;
+pub mod export;
+pub mod mfa;
+pub mod params;
+pub mod prompt;
 pub mod show;
+pub mod totp;
+pub mod warm;
+pub use self::arn::{Arn, Partition};
 pub use self::collection::ProfileMap;
 use crate::error::Error::*;
 use crate::error::*;
+use crate::server::daemon::DaemonStatus;
+use crate::shell::Shell;
 use ansi_term::Color;
 use chrono::{DateTime, Duration, FixedOffset, Local, Utc};
 use prettytable::*;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
 const ACTIVE_COLOUR: Color = Color::Green;
 const INACTIVE_COLOUR: Color = Color::Red;
@@ -19,6 +31,14 @@ pub struct AccessKey {
     pub mfa_serial: Option<String>,
 }
 
+/// Long-term keys printed by the `credential_process` command, e.g. a vault wrapper.
+#[derive(Debug, Default, PartialEq)]
+pub struct ProcessKeys {
+    pub command: String,
+    pub mfa_serial: Option<String>,
+    pub timeout: std::time::Duration,
+}
+
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct Credential {
     pub access_key_id: String,
@@ -30,6 +50,9 @@ pub struct Credential {
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub enum ProfileType {
     AssumeRole(String),
+    CredentialSource(CredentialSource),
+    Sso,
+    WebIdentity,
     SessionWithMFA,
     Keys,
     None,
@@ -53,25 +76,105 @@ impl Credential {
     }
 
     pub fn alive(&self) -> bool {
-        self.life().gt(&chrono::Duration::seconds(0))
+        self.alive_for(Duration::zero())
+    }
+
+    pub fn alive_for(&self, margin: Duration) -> bool {
+        self.life().gt(&margin)
+    }
+
+    /// Remaining life like ` 1 hour 5 minutes 3 seconds`.
+    pub fn life_str(&self) -> String {
+        let life = self.life();
+        format!(
+            "{}{}{}",
+            duration_to_string(life.num_hours(), "hour", "hours"),
+            duration_to_string(life.num_minutes() % 60, "minute", "minutes"),
+            duration_to_string(life.num_seconds() % 60, "second", "seconds")
+        )
     }
 
     pub fn local_expired_at_str(&self) -> String {
-        format!("{}", self.expiration.with_timezone(&Local).to_string())
+        self.expiration.with_timezone(&Local).to_string()
     }
 }
 
-#[derive(Debug, Default, PartialEq)]
+#[derive(Debug, PartialEq)]
 pub struct AssumedRole {
-    pub role_arn: String,
-    pub mfa_serial: String,
+    pub role_arn: Arn,
+    pub mfa_serial: Option<String>,
     pub source_profile: String,
 }
 
+/// `credential_source` of a role profile, where the credentials to assume the role come from.
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum CredentialSource {
+    Environment,
+    Ec2InstanceMetadata,
+    EcsContainer,
+}
+
+impl FromStr for CredentialSource {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "Environment" => Ok(CredentialSource::Environment),
+            "Ec2InstanceMetadata" => Ok(CredentialSource::Ec2InstanceMetadata),
+            "EcsContainer" => Ok(CredentialSource::EcsContainer),
+            _ => Err(ConfigFileError(format!(
+                "credential_source {:?} is not one of Environment, Ec2InstanceMetadata or EcsContainer.",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct SourcedRole {
+    pub role_arn: Arn,
+    pub mfa_serial: Option<String>,
+    pub credential_source: CredentialSource,
+}
+
+/// Role of an account in IAM Identity Center, from `sso_*` keys and `[sso-session]` section.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SsoRole {
+    pub start_url: String,
+    pub region: String,
+    pub account_id: String,
+    pub role_name: String,
+    pub session_name: Option<String>,
+}
+
+impl SsoRole {
+    /// The token cache is keyed by the sso-session name, or by the start url for legacy profiles.
+    pub fn cache_key(&self) -> &str {
+        self.session_name.as_deref().unwrap_or(&self.start_url)
+    }
+}
+
+/// Role assumed with the OIDC token in `web_identity_token_file`, like GitHub Actions and EKS.
+#[derive(Debug, PartialEq)]
+pub struct WebIdentityRole {
+    pub role_arn: Arn,
+    pub token_file: String,
+}
+
 #[derive(Debug, PartialEq)]
 pub enum Access {
     AccessKey(AccessKey),
+    CredentialProcess(ProcessKeys),
     AssumeRole(AssumedRole),
+    CredentialSource(SourcedRole),
+    Sso(SsoRole),
+    WebIdentity(WebIdentityRole),
+}
+
+pub struct Environment {
+    pub exports: Vec<(&'static str, String)>,
+    pub unset: Vec<&'static str>,
+    pub message: String,
 }
 
 #[derive(Debug, Default, PartialEq)]
@@ -81,15 +184,58 @@ pub struct Profile {
     pub region: Option<String>,
     pub access: Option<Access>,
     pub credential: Option<Credential>,
+    pub session_profile: Option<String>,
+    pub session_params: params::SessionParams,
+    /// command printing the mfa token, run when the token is not given.
+    pub mfa_process: Option<String>,
+    pub sts_endpoint: StsEndpoint,
+    pub http: HttpSettings,
+}
+
+/// Settings which decide where STS requests of the profile are sent.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct StsEndpoint {
+    /// `--endpoint-url`, which is preferred to any other setting.
+    pub override_url: Option<String>,
+    /// `endpoint_url` of the profile.
+    pub endpoint_url: Option<String>,
+    /// `sts_regional_endpoints` of the profile, `regional` or `legacy`.
+    pub regional: Option<String>,
+}
+
+/// Settings of the HTTPS connection to AWS. Proxies are read from environment variables.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HttpSettings {
+    /// `--ca-bundle`, which is preferred to `AWS_CA_BUNDLE` and the profile.
+    pub override_ca_bundle: Option<String>,
+    /// `ca_bundle` of the profile.
+    pub ca_bundle: Option<String>,
+    /// `retry_mode` of the profile, `legacy`, `standard` or `adaptive`.
+    pub retry_mode: Option<String>,
+    /// `max_attempts` of the profile, including the first request.
+    pub max_attempts: Option<u32>,
+    /// `connect_timeout` of the profile in seconds.
+    pub connect_timeout: Option<u64>,
+    /// `read_timeout` of the profile in seconds.
+    pub read_timeout: Option<u64>,
 }
 
 impl Profile {
-    pub fn print_table(profile_map: &ProfileMap) {
+    /// `daemon` is the status of the running daemon, to mark the sessions it keeps warm.
+    pub fn print_table(profile_map: &ProfileMap, daemon: Option<DaemonStatus>) {
         let mut profiles = profile_map.profiles();
-        profiles.sort_by(|a, b| a.order.cmp(&b.order));
+        profiles.sort_by_key(|p| p.order);
 
         let mut table = Table::new();
-        table.set_titles(row!["id", "profile", "region", "type", "credential"]);
+        table.set_titles(row![
+            "id",
+            "profile",
+            "region",
+            "account",
+            "role",
+            "type",
+            "credential"
+        ]);
         table.set_format(*format::consts::FORMAT_NO_BORDER_LINE_SEPARATOR);
         for profile in profiles.iter() {
             let colour = if profile.available() {
@@ -101,8 +247,15 @@ impl Profile {
                 profile.order + 1,
                 colour.paint(profile.profile_name.clone()),
                 profile.region_str(),
+                profile.account_str(),
+                profile.role_str(),
                 profile.profile_type_str(),
-                colour.paint(profile.credential_str()),
+                colour.paint(match &daemon {
+                    Some(status) if status.refreshes(&profile.profile_name) => {
+                        format!("{} (auto-refresh on)", profile.credential_str())
+                    }
+                    _ => profile.credential_str(),
+                }),
             ]);
         }
         table.printstd();
@@ -115,82 +268,167 @@ impl Profile {
         }
     }
 
-    pub fn assumable(&self, profile_map: &ProfileMap) -> bool {
-        match self.profile_type() {
-            ProfileType::AssumeRole(assumed_role) => match &self.credential {
-                Some(cred) => {
-                    !cred.alive()
-                        && match profile_map.get(&assumed_role) {
-                            Ok(role) => role.available(),
-                            _ => false,
-                        }
-                }
-                None => true,
-            },
+    /// Whether this is a role or sso profile whose session is expired or expires within `margin`.
+    pub fn needs_refresh(&self, margin: Duration) -> bool {
+        matches!(
+            self.access,
+            Some(Access::AssumeRole(_))
+                | Some(Access::CredentialSource(_))
+                | Some(Access::Sso(_))
+                | Some(Access::WebIdentity(_))
+        ) && !self
+            .credential
+            .as_ref()
+            .is_some_and(|cred| cred.alive_for(margin))
+    }
+
+    /// Whether the role can be assumed without mfa token, from the alive session of the source or its access keys.
+    pub fn assumable(&self, source: &Profile, source_alive: bool) -> bool {
+        match &self.access {
+            Some(Access::AssumeRole(role)) => {
+                source_alive
+                    || role.mfa_serial.is_none()
+                        && source.mfa_serial().is_none()
+                        && matches!(
+                            source.access,
+                            Some(Access::AccessKey(_)) | Some(Access::CredentialProcess(_))
+                        )
+            }
             _ => false,
         }
     }
 
-    pub fn export(&self) -> Result<String> {
+    /// `mfa_serial` of the profile, for both of keys and roles.
+    pub fn mfa_serial(&self) -> Option<&str> {
+        match &self.access {
+            Some(Access::AccessKey(AccessKey { mfa_serial, .. }))
+            | Some(Access::CredentialProcess(ProcessKeys { mfa_serial, .. }))
+            | Some(Access::AssumeRole(AssumedRole { mfa_serial, .. }))
+            | Some(Access::CredentialSource(SourcedRole { mfa_serial, .. })) => {
+                mfa_serial.as_deref()
+            }
+            _ => None,
+        }
+    }
+
+    /// Account of the role, or of the mfa device of keys.
+    fn account_str(&self) -> String {
+        match &self.access {
+            Some(Access::AssumeRole(AssumedRole { role_arn, .. }))
+            | Some(Access::CredentialSource(SourcedRole { role_arn, .. }))
+            | Some(Access::WebIdentity(WebIdentityRole { role_arn, .. })) => {
+                role_arn.account_id.clone()
+            }
+            Some(Access::Sso(role)) => role.account_id.clone(),
+            _ => self
+                .mfa_serial()
+                .and_then(|serial| serial.parse::<Arn>().ok())
+                .map_or_else(|| "-".to_string(), |arn| arn.account_id),
+        }
+    }
+
+    fn role_str(&self) -> String {
+        match &self.access {
+            Some(Access::AssumeRole(AssumedRole { role_arn, .. }))
+            | Some(Access::CredentialSource(SourcedRole { role_arn, .. }))
+            | Some(Access::WebIdentity(WebIdentityRole { role_arn, .. })) => role_arn.name.clone(),
+            Some(Access::Sso(role)) => role.role_name.clone(),
+            _ => "-".to_string(),
+        }
+    }
+
+    /// Partition of the role, or of the mfa device of keys.
+    pub fn partition(&self) -> Option<Partition> {
+        match &self.access {
+            Some(Access::AssumeRole(AssumedRole { role_arn, .. }))
+            | Some(Access::CredentialSource(SourcedRole { role_arn, .. }))
+            | Some(Access::WebIdentity(WebIdentityRole { role_arn, .. })) => {
+                Some(role_arn.partition)
+            }
+            _ => self
+                .mfa_serial()
+                .and_then(|serial| serial.parse::<Arn>().ok())
+                .map(|arn| arn.partition),
+        }
+    }
+
+    pub fn export(&self, shell: Shell) -> Result<String> {
+        let env = self.environment()?;
+        Ok(export::rc(shell, env.exports, env.unset, vec![&env.message]))
+    }
+
+    /// Environment variables to set and unset to use this profile.
+    pub fn environment(&self) -> Result<Environment> {
         match &self.credential {
             Some(cred) => {
                 if cred.alive() {
-                    let mut exports = vec![
-                        ("AWS_ACCESS_KEY_ID", cred.access_key_id.clone()),
-                        ("AWS_SECRET_ACCESS_KEY", cred.secret_access_key.clone()),
-                        ("AWS_SESSION_TOKEN", cred.session_token.clone()),
-                    ];
-                    let mut unset = vec!["AWS_PROFILE"];
-                    self.add_region(&mut exports, &mut unset);
-                    Ok(export::rc(
-                        exports,
-                        unset,
-                        vec![&format!(
+                    let mut env = Environment {
+                        exports: vec![
+                            ("AWS_ACCESS_KEY_ID", cred.access_key_id.clone()),
+                            ("AWS_SECRET_ACCESS_KEY", cred.secret_access_key.clone()),
+                            ("AWS_SESSION_TOKEN", cred.session_token.clone()),
+                        ],
+                        unset: vec!["AWS_PROFILE"],
+                        message: format!(
                             "set access_key_id, secret_access_key, session_token to env for profile '{}'",
                             self.profile_name
-                        )],
-                    ))
+                        ),
+                    };
+                    self.add_region(&mut env);
+                    Ok(env)
                 } else {
                     Err(SessionExpiredError(cred.local_expired_at_str()))
                 }
             }
             None => match self.profile_type() {
                 ProfileType::Keys | ProfileType::None => {
-                    let mut exports = vec![("AWS_PROFILE", self.profile_name.clone())];
-                    let mut unset = vec![
-                        "AWS_ACCESS_KEY_ID",
-                        "AWS_SECRET_ACCESS_KEY",
-                        "AWS_SESSION_TOKEN",
-                    ];
-                    self.add_region(&mut exports, &mut unset);
-                    Ok(export::rc(
-                        exports,
-                        unset,
-                        vec![&format!(
-                            "set AWS_PROFILE for profile '{}'",
-                            self.profile_name
-                        )],
-                    ))
-                }
-                ProfileType::AssumeRole(_) | ProfileType::SessionWithMFA => {
-                    Err(ProfileNotSignedIn(format!("{}", self.profile_name)))
+                    let mut env = Environment {
+                        exports: vec![("AWS_PROFILE", self.profile_name.clone())],
+                        unset: vec![
+                            "AWS_ACCESS_KEY_ID",
+                            "AWS_SECRET_ACCESS_KEY",
+                            "AWS_SESSION_TOKEN",
+                        ],
+                        message: format!("set AWS_PROFILE for profile '{}'", self.profile_name),
+                    };
+                    self.add_region(&mut env);
+                    Ok(env)
                 }
+                ProfileType::AssumeRole(_)
+                | ProfileType::CredentialSource(_)
+                | ProfileType::Sso
+                | ProfileType::WebIdentity
+                | ProfileType::SessionWithMFA => Err(ProfileNotSignedIn(self.profile_name.clone())),
             },
         }
     }
 
-    fn add_region(&self, exports: &mut Vec<(&str, String)>, unset: &mut Vec<&str>) {
+    fn add_region(&self, env: &mut Environment) {
         match &self.region {
-            Some(region) => exports.push(("AWS_DEFAULT_REGION", region.clone())),
-            None => unset.push("AWS_DEFAULT_REGION"),
+            Some(region) => env.exports.push(("AWS_DEFAULT_REGION", region.clone())),
+            None => env.unset.push("AWS_DEFAULT_REGION"),
         }
     }
 
     fn profile_type_str(&self) -> String {
         match &self.profile_type() {
             ProfileType::AssumeRole(assumed_role) => format!("Assume role from {}", assumed_role),
-            ProfileType::SessionWithMFA => "Access key with mfa device".to_string(),
-            ProfileType::Keys => "Access key".to_string(),
+            ProfileType::CredentialSource(source) => format!("Assume role from {:?}", source),
+            ProfileType::Sso => match &self.access {
+                Some(Access::Sso(sso)) => format!("SSO {} in {}", sso.role_name, sso.account_id),
+                _ => "SSO".to_string(),
+            },
+            ProfileType::WebIdentity => "Assume role with web identity".to_string(),
+            ProfileType::SessionWithMFA => match &self.access {
+                Some(Access::CredentialProcess(_)) => "Credential process with mfa device",
+                _ => "Access key with mfa device",
+            }
+            .to_string(),
+            ProfileType::Keys => match &self.access {
+                Some(Access::CredentialProcess(_)) => "Credential process",
+                _ => "Access key",
+            }
+            .to_string(),
             ProfileType::None => "".to_string(),
         }
     }
@@ -200,7 +438,13 @@ impl Profile {
             Some(Access::AssumeRole(assumed_role)) => {
                 ProfileType::AssumeRole(assumed_role.source_profile.to_string())
             }
-            Some(Access::AccessKey(access_key)) => match access_key.mfa_serial {
+            Some(Access::CredentialSource(role)) => {
+                ProfileType::CredentialSource(role.credential_source)
+            }
+            Some(Access::Sso(_)) => ProfileType::Sso,
+            Some(Access::WebIdentity(_)) => ProfileType::WebIdentity,
+            Some(Access::AccessKey(AccessKey { mfa_serial, .. }))
+            | Some(Access::CredentialProcess(ProcessKeys { mfa_serial, .. })) => match mfa_serial {
                 Some(_) => ProfileType::SessionWithMFA,
                 None => ProfileType::Keys,
             },
@@ -211,14 +455,8 @@ impl Profile {
     fn credential_str(&self) -> String {
         match &self.credential {
             Some(cred) => {
-                let life = cred.life();
                 if cred.alive() {
-                    format!(
-                        "{}{}{}",
-                        duration_to_string(life.num_hours(), "hour", "hours"),
-                        duration_to_string(life.num_minutes() % 60, "minute", "minutes"),
-                        duration_to_string(life.num_seconds() % 60, "second", "seconds")
-                    )
+                    cred.life_str()
                 } else {
                     format!("expired at {}", cred.local_expired_at_str())
                 }
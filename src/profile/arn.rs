@@ -0,0 +1,185 @@
+use crate::error::Error::*;
+use crate::error::*;
+use std::fmt;
+use std::str::FromStr;
+
+/// AWS partition, which decides the domain of endpoints and the default region.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Partition {
+    Aws,
+    AwsCn,
+    AwsUsGov,
+}
+
+impl Partition {
+    pub fn of_region(region: &str) -> Self {
+        if region.starts_with("cn-") {
+            Partition::AwsCn
+        } else if region.starts_with("us-gov-") {
+            Partition::AwsUsGov
+        } else {
+            Partition::Aws
+        }
+    }
+
+    pub fn default_region(self) -> &'static str {
+        match self {
+            Partition::Aws => "us-east-1",
+            Partition::AwsCn => "cn-north-1",
+            Partition::AwsUsGov => "us-gov-west-1",
+        }
+    }
+
+    pub fn dns_suffix(self) -> &'static str {
+        match self {
+            Partition::AwsCn => "amazonaws.com.cn",
+            Partition::Aws | Partition::AwsUsGov => "amazonaws.com",
+        }
+    }
+}
+
+impl FromStr for Partition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "aws" => Ok(Partition::Aws),
+            "aws-cn" => Ok(Partition::AwsCn),
+            "aws-us-gov" => Ok(Partition::AwsUsGov),
+            _ => Err(ConfigFileError(format!("partition {:?} is not known.", s))),
+        }
+    }
+}
+
+impl fmt::Display for Partition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Partition::Aws => "aws",
+            Partition::AwsCn => "aws-cn",
+            Partition::AwsUsGov => "aws-us-gov",
+        })
+    }
+}
+
+/// `arn:partition:service:region:account-id:resource-type/name` of an IAM role or mfa device.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Arn {
+    pub partition: Partition,
+    pub service: String,
+    pub region: String,
+    pub account_id: String,
+    pub resource_type: String,
+    /// name after the path, e.g. `Admin` of `role/ops/Admin`.
+    pub name: String,
+    resource: String,
+}
+
+impl Arn {
+    /// Parses an ARN of IAM `resource_type`. `key` names the setting in the error.
+    pub fn parse_iam(value: &str, key: &str, resource_type: &str) -> Result<Self> {
+        let arn = Self::parse(value).map_err(|e| ConfigFileError(format!("{} {}", key, e)))?;
+        if arn.service != "iam" || arn.resource_type != resource_type {
+            return Err(ConfigFileError(format!(
+                "{} {:?} is not an ARN of iam {}.",
+                key, value, resource_type
+            )));
+        }
+        Ok(arn)
+    }
+
+    fn parse(s: &str) -> std::result::Result<Self, String> {
+        let invalid = |reason: &str| format!("{:?} is not a valid ARN, {}.", s, reason);
+        let parts: Vec<&str> = s.splitn(6, ':').collect();
+        if parts.len() != 6 || parts[0] != "arn" {
+            return Err(invalid(
+                "it must be arn:partition:service:region:account:resource",
+            ));
+        }
+        let partition = parts[1]
+            .parse()
+            .map_err(|_| invalid("the partition is not aws, aws-cn nor aws-us-gov"))?;
+        let account_id = parts[4];
+        if account_id.len() != 12 || !account_id.chars().all(|c| c.is_ascii_digit()) {
+            return Err(invalid("the account id is not 12 digits"));
+        }
+        let resource = parts[5];
+        let (resource_type, path) = resource
+            .split_once('/')
+            .ok_or_else(|| invalid("the resource has no name"))?;
+        let name = path.rsplit('/').next().unwrap_or_default();
+        if parts[2].is_empty() || resource_type.is_empty() || name.is_empty() {
+            return Err(invalid("the service or resource is empty"));
+        }
+        Ok(Arn {
+            partition,
+            service: parts[2].to_owned(),
+            region: parts[3].to_owned(),
+            account_id: account_id.to_owned(),
+            resource_type: resource_type.to_owned(),
+            name: name.to_owned(),
+            resource: resource.to_owned(),
+        })
+    }
+}
+
+impl FromStr for Arn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Self::parse(s).map_err(ConfigFileError)
+    }
+}
+
+impl fmt::Display for Arn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "arn:{}:{}:{}:{}:{}",
+            self.partition, self.service, self.region, self.account_id, self.resource
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_role() {
+        let arn = Arn::parse_iam(
+            "arn:aws-cn:iam::123456789012:role/ops/Admin",
+            "role_arn",
+            "role",
+        )
+        .unwrap();
+        assert_eq!(arn.partition, Partition::AwsCn);
+        assert_eq!(arn.account_id, "123456789012");
+        assert_eq!(arn.resource_type, "role");
+        assert_eq!(arn.name, "Admin");
+        assert_eq!(
+            arn.to_string(),
+            "arn:aws-cn:iam::123456789012:role/ops/Admin"
+        );
+    }
+
+    #[test]
+    fn parse_errors() {
+        let parse = |value| Arn::parse_iam(value, "role_arn", "role");
+        assert!(parse("arn:aws:iam::123456789012:mfa/me").is_err());
+        assert!(parse("arn:aws:s3::123456789012:role/x").is_err());
+        assert!(parse("arn:aws:iam::12345:role/x").is_err());
+        assert!(parse("arn:aws-xx:iam::123456789012:role/x").is_err());
+        assert!(parse("arn:aws:iam::123456789012:role/").is_err());
+        assert!(parse("arn:aws:iam::123456789012:role").is_err());
+        assert!(parse("role/x").is_err());
+    }
+
+    #[test]
+    fn partition() {
+        assert_eq!(Partition::of_region("cn-northwest-1"), Partition::AwsCn);
+        assert_eq!(Partition::of_region("us-gov-east-1"), Partition::AwsUsGov);
+        assert_eq!(Partition::of_region("us-east-1"), Partition::Aws);
+        assert_eq!(Partition::AwsUsGov.default_region(), "us-gov-west-1");
+        assert_eq!(Partition::AwsCn.dns_suffix(), "amazonaws.com.cn");
+    }
+}
@@ -1,36 +1,59 @@
-use super::Profile;
+use super::{mfa, Access, Profile, ProfileType};
 use crate::error::Error::*;
 use crate::error::*;
+use crate::file::mfa_history::record_used_token;
+use crate::shell::Shell;
+use chrono::Duration;
 use std::collections::hash_map::IterMut;
 use std::collections::HashMap;
 
 pub struct ProfileMap {
     map: HashMap<String, Profile>,
+    /// errors of the profiles which could not be read, reported only when they are used.
+    invalid: HashMap<String, String>,
 }
 
 impl ProfileMap {
     pub fn new() -> Self {
         ProfileMap {
             map: HashMap::new(),
+            invalid: HashMap::new(),
         }
     }
 
     pub fn get_mut(&mut self, name: &str) -> Result<&mut Profile> {
-        Ok(self
-            .map
+        let invalid = &self.invalid;
+        self.map
             .get_mut(name)
-            .ok_or(ProfileNotFound(name.to_owned()))?)
+            .ok_or_else(|| not_found(invalid, name))
     }
 
     pub fn get(&self, name: &str) -> Result<&Profile> {
-        Ok(self.map.get(name).ok_or(ProfileNotFound(name.to_owned()))?)
+        self.map
+            .get(name)
+            .ok_or_else(|| not_found(&self.invalid, name))
     }
 
     pub fn insert(&mut self, name: &str, profile: Profile) {
         self.map.insert(name.to_string(), profile);
     }
 
-    pub fn iter_mut(&mut self) -> IterMut<String, Profile> {
+    pub fn insert_invalid(&mut self, name: &str, message: String) {
+        self.invalid.insert(name.to_string(), message);
+    }
+
+    /// Profiles with a bad setting and the reasons, sorted by name.
+    pub fn invalid_profiles(&self) -> Vec<(&str, &str)> {
+        let mut invalid: Vec<_> = self
+            .invalid
+            .iter()
+            .map(|(name, message)| (name.as_str(), message.as_str()))
+            .collect();
+        invalid.sort();
+        invalid
+    }
+
+    pub fn iter_mut(&mut self) -> IterMut<'_, String, Profile> {
         self.map.iter_mut()
     }
 
@@ -47,15 +70,675 @@ impl ProfileMap {
         None
     }
 
+    /// Sends STS requests of all profiles to the url, for `--endpoint-url`.
+    pub fn override_endpoint_url(&mut self, url: &str) {
+        for profile in self.map.values_mut() {
+            profile.sts_endpoint.override_url = Some(url.to_owned());
+        }
+    }
+
+    /// Trusts the certificates of the file in requests of all profiles, for `--ca-bundle`.
+    pub fn override_ca_bundle(&mut self, path: &str) {
+        for profile in self.map.values_mut() {
+            profile.http.override_ca_bundle = Some(path.to_owned());
+        }
+    }
+
     pub fn profiles(&self) -> Vec<&Profile> {
         self.map.values().collect::<Vec<&Profile>>()
     }
 
-    pub async fn print_export(&mut self, profile_name: &str) -> Result<()> {
-        if !self.get(profile_name)?.available() && self.get(profile_name)?.assumable(self) {
-            crate::client::assume_role::send(profile_name, self, None).await?;
+    /// Returns the profile, assuming role first if it is expired and its source is still alive.
+    pub async fn resolve(&mut self, profile_name: &str) -> Result<&Profile> {
+        self.refresh(profile_name, Duration::zero()).await
+    }
+
+    /// Same as `resolve`, but assumes role also if the session expires within `margin`.
+    /// When STS is unreachable, the cached session is used while it is alive.
+    pub async fn refresh(&mut self, profile_name: &str, margin: Duration) -> Result<&Profile> {
+        let result = self.assume_chain(profile_name, margin, None, false).await;
+        if let Err(e) = result.map(|_| ()) {
+            match (e, &self.get(profile_name)?.credential) {
+                (StsUnreachable(reason), Some(cred)) if cred.alive() => eprintln!(
+                    "STS unreachable, cached session of {} still valid for{}. {}",
+                    profile_name,
+                    cred.life_str(),
+                    reason
+                ),
+                (e, _) => return Err(e),
+            }
+        }
+        self.get(profile_name)
+    }
+
+    /// Assumes the role with the mfa token, and the expired roles it is chained from.
+    /// The token is used at the first hop which needs it.
+    pub async fn sign_in(&mut self, profile_name: &str, token: Option<String>) -> Result<&Profile> {
+        self.assume_chain(profile_name, Duration::zero(), token, true)
+            .await
+    }
+
+    /// Whether the session of the role can be re-issued without mfa token, assuming the expired roles
+    /// it is chained from too. A session signed in with mfa keeps the roles chained from it assumable.
+    pub fn assumable(&self, profile_name: &str) -> Result<bool> {
+        let mut assumable = false;
+        let mut source: Option<(&Profile, bool)> = None;
+        for name in self.source_chain(profile_name)? {
+            let profile = self.get(&name)?;
+            assumable = match (&profile.access, source) {
+                (Some(Access::AssumeRole(_)), Some((source, alive))) => {
+                    profile.assumable(source, alive)
+                }
+                (Some(Access::CredentialSource(role)), _) => role.mfa_serial.is_none(),
+                _ => false,
+            };
+            let alive = profile.credential.as_ref().is_some_and(|c| c.alive());
+            source = Some((profile, alive || assumable));
+        }
+        Ok(assumable)
+    }
+
+    /// `mfa_process` of the profile, or of the nearest profile it is chained from.
+    pub fn mfa_process(&self, profile_name: &str) -> Result<Option<&str>> {
+        for name in self.source_chain(profile_name)?.iter().rev() {
+            if let Some(command) = &self.get(name)?.mfa_process {
+                return Ok(Some(command));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The first hop in the chain which `sign_in` uses the token at, and its `mfa_serial`.
+    pub fn sign_in_mfa_serial(&self, profile_name: &str) -> Result<Option<(String, String)>> {
+        for name in self.source_chain(profile_name)? {
+            let profile = self.get(&name)?;
+            if profile.needs_refresh(Duration::zero()) || name == profile_name {
+                if let Some(source) = self.source_needing_session(&name)? {
+                    let serial = self.get(&source)?.mfa_serial().map(|s| s.to_owned());
+                    return Ok(serial.map(|serial| (source, serial)));
+                }
+                if let Some(serial) = profile.mfa_serial() {
+                    return Ok(Some((name.clone(), serial.to_owned())));
+                }
+            }
+        }
+        Ok(None)
+    }
+
+    async fn assume_chain(
+        &mut self,
+        profile_name: &str,
+        margin: Duration,
+        mut token: Option<String>,
+        force: bool,
+    ) -> Result<&Profile> {
+        for name in self.source_chain(profile_name)? {
+            let profile = self.get(&name)?;
+            if profile.needs_refresh(margin) || force && name == profile_name {
+                if let Some(source) = self.source_needing_session(&name)? {
+                    self.create_session(&source, &mut token).await?;
+                }
+                let profile = self.get(&name)?;
+                match profile.access {
+                    Some(Access::Sso(_)) => crate::client::sso::send(&name, self).await?,
+                    Some(Access::WebIdentity(_)) => {
+                        crate::client::web_identity::send(&name, self).await?
+                    }
+                    _ => self.assume_role(&name, &mut token).await?,
+                }
+            }
+        }
+        self.get(profile_name)
+    }
+
+    /// Assumes role of a hop. A role which needs mfa token without `in` gets it from `mfa_process`.
+    async fn assume_role(&mut self, name: &str, token: &mut Option<String>) -> Result<()> {
+        match crate::client::assume_role::send(name, self, token).await {
+            Err(MfaTokenRequired(profile_name)) => match mfa::process_token(self, name).await? {
+                Some((mfa_serial, process_token)) => {
+                    crate::client::assume_role::send(name, self, &mut Some(process_token.clone()))
+                        .await?;
+                    record_used_token(&mfa_serial, &process_token)
+                }
+                None => Err(MfaTokenRequired(profile_name)),
+            },
+            result => result,
         }
-        println!("{}", self.get(profile_name)?.export()?);
+    }
+
+    /// Source of the role which has to be signed in with mfa first. A role without `mfa_serial`
+    /// inherits mfa of its source keys, and is never assumed with the keys themselves.
+    fn source_needing_session(&self, name: &str) -> Result<Option<String>> {
+        if let Some(Access::AssumeRole(role)) = &self.get(name)?.access {
+            let source = self.get(&role.source_profile)?;
+            if role.mfa_serial.is_none()
+                && source.profile_type() == ProfileType::SessionWithMFA
+                && !source.credential.as_ref().is_some_and(|c| c.alive())
+            {
+                return Ok(Some(role.source_profile.clone()));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Gets the session of keys with mfa, with the token or `mfa_process`.
+    async fn create_session(&mut self, name: &str, token: &mut Option<String>) -> Result<()> {
+        match token.take() {
+            Some(token) => crate::client::create_session::send(self.get_mut(name)?, &token).await,
+            None => match mfa::process_token(self, name).await? {
+                Some((mfa_serial, token)) => {
+                    crate::client::create_session::send(self.get_mut(name)?, &token).await?;
+                    record_used_token(&mfa_serial, &token)
+                }
+                None => Err(MfaTokenRequired(name.to_owned())),
+            },
+        }
+    }
+
+    /// Names of the profiles from the root of `source_profile` to the profile itself.
+    pub fn source_chain(&self, profile_name: &str) -> Result<Vec<String>> {
+        let mut chain = vec![profile_name.to_owned()];
+        let mut profile = self.get(profile_name)?;
+        while let Some(Access::AssumeRole(role)) = &profile.access {
+            let source = &role.source_profile;
+            if chain.contains(source) {
+                chain.reverse();
+                return Err(RoleChainCycleError(format!(
+                    "{} -> {}",
+                    source,
+                    chain.join(" -> ")
+                )));
+            }
+            chain.push(source.to_owned());
+            profile = self.get(source)?;
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+
+    /// Role profiles chained from the profile, grouped by the number of hops from it.
+    /// Roles of a group are assumed from the sessions of the groups before it.
+    pub fn dependents(&self, profile_name: &str) -> Result<Vec<Vec<String>>> {
+        self.get(profile_name)?;
+        let mut groups: Vec<Vec<String>> = vec![];
+        for name in self.map.keys() {
+            let chain = match self.source_chain(name) {
+                Ok(chain) => chain,
+                Err(_) => continue,
+            };
+            if let Some(position) = chain.iter().position(|n| n == profile_name) {
+                let hops = chain.len() - 1 - position;
+                if hops == 0 {
+                    continue;
+                }
+                if groups.len() < hops {
+                    groups.resize(hops, vec![]);
+                }
+                groups[hops - 1].push(name.to_owned());
+            }
+        }
+        for group in groups.iter_mut() {
+            group.sort_by_key(|name| self.map[name].order);
+        }
+        Ok(groups)
+    }
+
+    pub async fn print_export(&mut self, profile_name: &str, shell: Shell) -> Result<()> {
+        println!("{}", self.resolve(profile_name).await?.export(shell)?);
         Ok(())
     }
 }
+
+fn not_found(invalid: &HashMap<String, String>, name: &str) -> Error {
+    match invalid.get(name) {
+        Some(message) => ConfigFileError(message.clone()),
+        None => ProfileNotFound(name.to_owned()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{AccessKey, AssumedRole, Credential, HttpSettings, ProcessKeys};
+    use chrono::Utc;
+
+    fn role(source_profile: &str) -> Profile {
+        Profile {
+            access: Some(Access::AssumeRole(AssumedRole {
+                role_arn: "arn:aws:iam::000000000000:role/admin".parse().unwrap(),
+                mfa_serial: None,
+                source_profile: source_profile.to_string(),
+            })),
+            ..Profile::default()
+        }
+    }
+
+    fn profile_map(profiles: Vec<(&str, Profile)>) -> ProfileMap {
+        let mut profile_map = ProfileMap::new();
+        for (name, profile) in profiles {
+            profile_map.insert(name, profile);
+        }
+        profile_map
+    }
+
+    #[test]
+    fn source_chain() {
+        let profile_map = profile_map(vec![
+            (
+                "base",
+                Profile {
+                    access: Some(Access::AccessKey(AccessKey::default())),
+                    ..Profile::default()
+                },
+            ),
+            ("a", role("base")),
+            ("b", role("a")),
+        ]);
+        assert_eq!(
+            profile_map.source_chain("b").unwrap(),
+            vec!["base", "a", "b"]
+        );
+        assert_eq!(profile_map.source_chain("base").unwrap(), vec!["base"]);
+    }
+
+    #[test]
+    fn source_chain_cycle() {
+        let profile_map = profile_map(vec![("a", role("c")), ("b", role("a")), ("c", role("b"))]);
+        match profile_map.source_chain("a") {
+            Err(RoleChainCycleError(path)) => assert_eq!(path, "a -> b -> c -> a"),
+            other => panic!("unexpected {:?}", other),
+        }
+        let profile_map = self::profile_map(vec![("a", role("a"))]);
+        assert!(profile_map.source_chain("a").is_err());
+    }
+
+    #[test]
+    fn source_chain_missing_source() {
+        let profile_map = profile_map(vec![("a", role("none"))]);
+        assert!(matches!(
+            profile_map.source_chain("a"),
+            Err(ProfileNotFound(name)) if name == "none"
+        ));
+    }
+
+    #[test]
+    fn mfa_process_of_chain() {
+        let profile_map = profile_map(vec![
+            (
+                "base",
+                Profile {
+                    access: Some(Access::AccessKey(AccessKey::default())),
+                    mfa_process: Some("ykman oath accounts code -s aws".to_string()),
+                    ..Profile::default()
+                },
+            ),
+            ("a", role("base")),
+            (
+                "b",
+                Profile {
+                    mfa_process: Some("pass otp aws".to_string()),
+                    ..role("a")
+                },
+            ),
+        ]);
+        assert_eq!(
+            profile_map.mfa_process("a").unwrap(),
+            Some("ykman oath accounts code -s aws")
+        );
+        assert_eq!(profile_map.mfa_process("b").unwrap(), Some("pass otp aws"));
+        let profile_map = self::profile_map(vec![("c", role("d")), ("d", Profile::default())]);
+        assert_eq!(profile_map.mfa_process("c").unwrap(), None);
+    }
+
+    #[test]
+    fn invalid_profile_fails_when_used() {
+        let mut profile_map = profile_map(vec![("a", role("base"))]);
+        profile_map.insert_invalid("base", "role_arn of profile base is invalid.".to_string());
+        assert!(matches!(
+            profile_map.get("base"),
+            Err(ConfigFileError(message)) if message.contains("role_arn")
+        ));
+        assert!(matches!(
+            profile_map.source_chain("a"),
+            Err(ConfigFileError(_))
+        ));
+        assert!(matches!(profile_map.get("c"), Err(ProfileNotFound(_))));
+        assert_eq!(
+            profile_map.invalid_profiles(),
+            vec![("base", "role_arn of profile base is invalid.")]
+        );
+    }
+
+    #[test]
+    fn mfa_process_of_first_hop() {
+        let serial = "arn:aws:iam::000000000000:mfa/me".to_string();
+        let profile_map = profile_map(vec![
+            (
+                "base",
+                Profile {
+                    access: Some(Access::AccessKey(AccessKey::default())),
+                    ..Profile::default()
+                },
+            ),
+            (
+                "a",
+                Profile {
+                    access: Some(Access::AssumeRole(AssumedRole {
+                        role_arn: "arn:aws:iam::000000000000:role/admin".parse().unwrap(),
+                        mfa_serial: Some(serial.clone()),
+                        source_profile: "base".to_string(),
+                    })),
+                    mfa_process: Some("ykman oath accounts code -s aws".to_string()),
+                    ..Profile::default()
+                },
+            ),
+            (
+                "b",
+                Profile {
+                    mfa_process: Some("pass otp other".to_string()),
+                    ..role("a")
+                },
+            ),
+        ]);
+        let (owner, mfa_serial) = profile_map.sign_in_mfa_serial("b").unwrap().unwrap();
+        assert_eq!((owner.as_str(), mfa_serial), ("a", serial));
+        assert_eq!(
+            profile_map.mfa_process(&owner).unwrap(),
+            Some("ykman oath accounts code -s aws")
+        );
+    }
+
+    #[test]
+    fn dependents() {
+        let profile_map = profile_map(vec![
+            (
+                "base",
+                Profile {
+                    access: Some(Access::AccessKey(AccessKey::default())),
+                    ..Profile::default()
+                },
+            ),
+            (
+                "a",
+                Profile {
+                    order: 2,
+                    ..role("base")
+                },
+            ),
+            (
+                "b",
+                Profile {
+                    order: 1,
+                    ..role("base")
+                },
+            ),
+            ("c", role("a")),
+            ("other", role("d")),
+            ("d", Profile::default()),
+        ]);
+        assert_eq!(
+            profile_map.dependents("base").unwrap(),
+            vec![vec!["b", "a"], vec!["c"]]
+        );
+        assert_eq!(profile_map.dependents("a").unwrap(), vec![vec!["c"]]);
+        assert!(profile_map.dependents("c").unwrap().is_empty());
+        assert!(profile_map.dependents("none").is_err());
+    }
+
+    #[test]
+    fn assumable() {
+        let session = |hours| Credential {
+            access_key_id: "ASIA".to_string(),
+            expiration: Utc::now() + Duration::hours(hours),
+            secret_access_key: "secret".to_string(),
+            session_token: "token".to_string(),
+        };
+        let keys = |mfa_serial: Option<&str>, hours: Option<i64>| Profile {
+            access: Some(Access::AccessKey(AccessKey {
+                mfa_serial: mfa_serial.map(|s| s.to_string()),
+                ..AccessKey::default()
+            })),
+            credential: hours.map(session),
+            ..Profile::default()
+        };
+        let with_mfa = |profile: Profile| match profile.access {
+            Some(Access::AssumeRole(role)) => Profile {
+                access: Some(Access::AssumeRole(AssumedRole {
+                    mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+                    ..role
+                })),
+                ..profile
+            },
+            _ => profile,
+        };
+        let serial = Some("arn:aws:iam::000000000000:mfa/me");
+
+        let signed_in = profile_map(vec![
+            ("base", keys(serial, Some(1))),
+            ("a", with_mfa(role("base"))),
+            ("b", role("a")),
+        ]);
+        assert!(signed_in.assumable("a").unwrap());
+        assert!(signed_in.assumable("b").unwrap());
+        assert!(!signed_in.assumable("base").unwrap());
+
+        let expired = profile_map(vec![
+            ("base", keys(serial, Some(-1))),
+            ("a", with_mfa(role("base"))),
+            ("b", role("a")),
+        ]);
+        assert!(!expired.assumable("a").unwrap());
+        assert!(!expired.assumable("b").unwrap());
+
+        let chained_session = profile_map(vec![
+            ("base", keys(serial, Some(-1))),
+            (
+                "a",
+                Profile {
+                    credential: Some(session(1)),
+                    ..with_mfa(role("base"))
+                },
+            ),
+            ("b", role("a")),
+        ]);
+        assert!(chained_session.assumable("b").unwrap());
+
+        let without_mfa = profile_map(vec![("base", keys(None, None)), ("a", role("base"))]);
+        assert!(without_mfa.assumable("a").unwrap());
+
+        // the role inherits mfa of the keys, so it waits for sign in.
+        let inherited = profile_map(vec![("base", keys(serial, Some(-1))), ("a", role("base"))]);
+        assert!(!inherited.assumable("a").unwrap());
+    }
+
+    #[test]
+    fn sources_needing_session() {
+        let keys = |mfa_serial: Option<&str>| Profile {
+            access: Some(Access::AccessKey(AccessKey {
+                mfa_serial: mfa_serial.map(|s| s.to_string()),
+                ..AccessKey::default()
+            })),
+            ..Profile::default()
+        };
+        let serial = Some("arn:aws:iam::000000000000:mfa/me");
+        let profile_map = profile_map(vec![("base", keys(serial)), ("a", role("base"))]);
+        assert_eq!(
+            profile_map.source_needing_session("a").unwrap(),
+            Some("base".to_string())
+        );
+        assert_eq!(profile_map.source_needing_session("base").unwrap(), None);
+        let profile_map = self::profile_map(vec![("base", keys(None)), ("a", role("base"))]);
+        assert_eq!(profile_map.source_needing_session("a").unwrap(), None);
+    }
+
+    static SIGNED_WITH: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(vec![]);
+
+    /// Answers GetSessionToken and AssumeRole, recording the action and the access key signing it.
+    fn sts(parts: hyper::http::request::Parts, body: String) -> (hyper::StatusCode, String) {
+        let key = parts
+            .headers
+            .get("authorization")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.split("Credential=").nth(1))
+            .and_then(|value| value.split('/').next())
+            .unwrap_or_default()
+            .to_string();
+        let action = if body.contains("Action=GetSessionToken") {
+            "GetSessionToken"
+        } else {
+            "AssumeRole"
+        };
+        SIGNED_WITH
+            .lock()
+            .unwrap()
+            .push(format!("{} {}", action, key));
+        (
+            hyper::StatusCode::OK,
+            format!(
+                r#"<{0}Response xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <{0}Result>
+    <Credentials>
+      <AccessKeyId>ASIA{0}</AccessKeyId>
+      <SecretAccessKey>secret</SecretAccessKey>
+      <SessionToken>token</SessionToken>
+      <Expiration>2999-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </{0}Result>
+  <ResponseMetadata>
+    <RequestId>00000000-0000-0000-0000-000000000000</RequestId>
+  </ResponseMetadata>
+</{0}Response>"#,
+                action
+            ),
+        )
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn role_inherits_mfa_of_source_keys() {
+        let url = crate::client::util::stand_in(sts);
+        let local = |profile: Profile| {
+            let mut profile = profile;
+            profile.sts_endpoint.override_url = Some(url.clone());
+            profile
+        };
+        let mut profile_map = profile_map(vec![
+            (
+                "base",
+                local(Profile {
+                    profile_name: "base".to_string(),
+                    access: Some(Access::CredentialProcess(ProcessKeys {
+                        command:
+                            r#"echo '{"Version":1,"AccessKeyId":"AKIAKEYS","SecretAccessKey":"s"}'"#
+                                .to_string(),
+                        mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+                        timeout: std::time::Duration::from_secs(10),
+                    })),
+                    ..Profile::default()
+                }),
+            ),
+            (
+                "a",
+                local(Profile {
+                    profile_name: "a".to_string(),
+                    ..role("base")
+                }),
+            ),
+        ]);
+        assert_eq!(
+            profile_map.sign_in_mfa_serial("a").unwrap(),
+            Some((
+                "base".to_string(),
+                "arn:aws:iam::000000000000:mfa/me".to_string()
+            ))
+        );
+        assert!(matches!(
+            profile_map.resolve("a").await,
+            Err(MfaTokenRequired(name)) if name == "base"
+        ));
+
+        profile_map
+            .sign_in("a", Some("123456".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            *SIGNED_WITH.lock().unwrap(),
+            vec![
+                "GetSessionToken AKIAKEYS".to_string(),
+                "AssumeRole ASIAGetSessionToken".to_string(),
+            ]
+        );
+        let credential = |name| {
+            profile_map
+                .get(name)
+                .unwrap()
+                .credential
+                .as_ref()
+                .unwrap()
+                .access_key_id
+                .clone()
+        };
+        assert_eq!(credential("base"), "ASIAGetSessionToken");
+        assert_eq!(credential("a"), "ASIAAssumeRole");
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refresh_offline() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        drop(listener);
+        let unreachable = |profile: Profile| {
+            let mut profile = Profile {
+                http: HttpSettings {
+                    max_attempts: Some(1),
+                    ..HttpSettings::default()
+                },
+                ..profile
+            };
+            profile.sts_endpoint.override_url = Some(url.clone());
+            profile
+        };
+        let session = |hours| Credential {
+            access_key_id: "ASIA".to_string(),
+            expiration: Utc::now() + Duration::hours(hours),
+            secret_access_key: "secret".to_string(),
+            session_token: "token".to_string(),
+        };
+        let mut profile_map = profile_map(vec![
+            (
+                "base",
+                unreachable(Profile {
+                    access: Some(Access::CredentialProcess(ProcessKeys {
+                        command:
+                            r#"echo '{"Version":1,"AccessKeyId":"AKIA","SecretAccessKey":"s"}'"#
+                                .to_string(),
+                        mfa_serial: None,
+                        timeout: std::time::Duration::from_secs(10),
+                    })),
+                    ..Profile::default()
+                }),
+            ),
+            (
+                "a",
+                unreachable(Profile {
+                    credential: Some(session(1)),
+                    ..role("base")
+                }),
+            ),
+            (
+                "b",
+                unreachable(Profile {
+                    credential: Some(session(-1)),
+                    ..role("base")
+                }),
+            ),
+        ]);
+        let profile = profile_map.refresh("a", Duration::hours(2)).await.unwrap();
+        assert!(profile.credential.as_ref().unwrap().alive());
+        assert!(matches!(
+            profile_map.refresh("b", Duration::zero()).await,
+            Err(StsUnreachable(_))
+        ));
+    }
+}
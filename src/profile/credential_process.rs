@@ -0,0 +1,250 @@
+use crate::error::Error::*;
+use crate::error::*;
+use crate::profile::{Access, Profile, ProfileType};
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use rusoto_credential::AwsCredentials;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Output format of `credential_process` which AWS CLI and SDKs read.
+/// https://docs.aws.amazon.com/cli/latest/userguide/cli-configure-sourcing-external.html
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ProcessCredential {
+    pub version: u8,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub session_token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expiration: Option<String>,
+}
+
+impl ProcessCredential {
+    pub async fn new(profile: &Profile) -> Result<Self> {
+        match &profile.credential {
+            Some(cred) => {
+                if cred.alive() {
+                    Ok(Self {
+                        version: 1,
+                        access_key_id: cred.access_key_id.clone(),
+                        secret_access_key: cred.secret_access_key.clone(),
+                        session_token: Some(cred.session_token.clone()),
+                        expiration: Some(expiration_str(&cred.expiration)),
+                    })
+                } else {
+                    Err(SessionExpiredError(cred.local_expired_at_str()))
+                }
+            }
+            None => match (&profile.access, profile.profile_type()) {
+                (Some(Access::AccessKey(key)), ProfileType::Keys) => Ok(Self {
+                    version: 1,
+                    access_key_id: key.access_key_id.clone(),
+                    secret_access_key: key.secret_access_key.clone(),
+                    session_token: None,
+                    expiration: None,
+                }),
+                (Some(Access::CredentialProcess(process)), ProfileType::Keys) => {
+                    Self::run(&process.command, process.timeout).await
+                }
+                _ => Err(ProfileNotSignedIn(profile.profile_name.clone())),
+            },
+        }
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| CredentialProcessError(e.to_string()))
+    }
+
+    /// Runs `credential_process` of a profile and reads its output.
+    pub async fn run(command: &str, timeout: Duration) -> Result<Self> {
+        let error = |message: String| CredentialProcessError(format!("`{}` {}", command, message));
+        let output = run_command(command, timeout).await.map_err(error)?;
+        let credential: Self = serde_json::from_str(&output)
+            .map_err(|e| error(format!("printed invalid JSON. {}", e)))?;
+        if credential.version != 1 {
+            return Err(error(format!(
+                "printed Version {}, only 1 is supported.",
+                credential.version
+            )));
+        }
+        Ok(credential)
+    }
+
+    pub fn aws_credentials(self) -> Result<AwsCredentials> {
+        let expiration = match &self.expiration {
+            Some(expiration) => Some(
+                DateTime::<FixedOffset>::parse_from_rfc3339(expiration)
+                    .map(|dt| dt.with_timezone(&Utc))
+                    .map_err(|e| CredentialProcessError(format!("invalid Expiration. {}", e)))?,
+            ),
+            None => None,
+        };
+        Ok(AwsCredentials::new(
+            self.access_key_id,
+            self.secret_access_key,
+            self.session_token,
+            expiration,
+        ))
+    }
+}
+
+/// Runs the command with the shell, and returns what it prints to stdout.
+/// stdin and stderr are passed through, for the prompts of vault wrappers and hardware keys.
+/// waits on the blocking pool, not to stall the other tasks of the runtime.
+pub async fn run_command(command: &str, timeout: Duration) -> std::result::Result<String, String> {
+    let command = command.to_owned();
+    tokio::task::spawn_blocking(move || wait_command(&command, timeout))
+        .await
+        .map_err(|e| e.to_string())?
+}
+
+fn wait_command(command: &str, timeout: Duration) -> std::result::Result<String, String> {
+    let mut child = shell_command(command)
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::inherit())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // stdout is read in another thread, so that the command never blocks on a full pipe.
+    let mut stdout = child.stdout.take().ok_or_else(|| "no stdout".to_string())?;
+    let reader = std::thread::spawn(move || {
+        let mut output = String::new();
+        stdout.read_to_string(&mut output).map(|_| output)
+    });
+
+    let deadline = Instant::now() + timeout;
+    let status = loop {
+        match child.try_wait().map_err(|e| e.to_string())? {
+            Some(status) => break status,
+            None if Instant::now() > deadline => {
+                child.kill().ok();
+                child.wait().ok();
+                return Err(format!("timed out after {} seconds.", timeout.as_secs()));
+            }
+            None => std::thread::sleep(Duration::from_millis(20)),
+        }
+    };
+    if !status.success() {
+        return Err(format!("exited with {}.", status));
+    }
+    reader
+        .join()
+        .map_err(|_| "failed to read output.".to_string())?
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(unix)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell_command(command: &str) -> Command {
+    let mut shell = Command::new("cmd");
+    shell.arg("/C").arg(command);
+    shell
+}
+
+fn expiration_str(expiration: &DateTime<Utc>) -> String {
+    expiration.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{AccessKey, Credential};
+    use chrono::TimeZone;
+
+    fn profile(credential: Option<Credential>, mfa_serial: Option<String>) -> Profile {
+        Profile {
+            profile_name: "foo".to_string(),
+            access: Some(Access::AccessKey(AccessKey {
+                access_key_id: "AKIA".to_string(),
+                secret_access_key: "secret".to_string(),
+                mfa_serial,
+            })),
+            credential,
+            ..Profile::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn session_json() {
+        let credential = Credential {
+            access_key_id: "ASIA".to_string(),
+            expiration: Utc.ymd(2999, 1, 2).and_hms(3, 4, 5),
+            secret_access_key: "session secret".to_string(),
+            session_token: "token".to_string(),
+        };
+        let profile = profile(Some(credential), Some("arn".to_string()));
+        assert_eq!(
+            ProcessCredential::new(&profile)
+                .await
+                .unwrap()
+                .to_json()
+                .unwrap(),
+            r#"{"Version":1,"AccessKeyId":"ASIA","SecretAccessKey":"session secret","SessionToken":"token","Expiration":"2999-01-02T03:04:05Z"}"#
+        );
+    }
+
+    #[tokio::test]
+    async fn keys_json() {
+        assert_eq!(
+            ProcessCredential::new(&profile(None, None))
+                .await
+                .unwrap()
+                .to_json()
+                .unwrap(),
+            r#"{"Version":1,"AccessKeyId":"AKIA","SecretAccessKey":"secret"}"#
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_process() {
+        let credential = ProcessCredential::run(
+            r#"echo 'prompt on stderr' >&2; echo '{"Version":1,"AccessKeyId":"AKIA","SecretAccessKey":"secret","Expiration":"2999-01-02T03:04:05Z"}'"#,
+            Duration::from_secs(10),
+        )
+        .await
+        .unwrap();
+        assert_eq!(credential.access_key_id, "AKIA");
+        let credentials = credential.aws_credentials().unwrap();
+        assert_eq!(
+            credentials.expires_at().unwrap(),
+            Utc.ymd(2999, 1, 2).and_hms(3, 4, 5)
+        );
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn run_process_errors() {
+        let run = |command| ProcessCredential::run(command, Duration::from_millis(500));
+        assert!(run("exit 3").await.is_err());
+        assert!(run("echo not json").await.is_err());
+        assert!(
+            run(r#"echo '{"Version":2,"AccessKeyId":"A","SecretAccessKey":"s"}'"#)
+                .await
+                .is_err()
+        );
+        match run("sleep 5").await {
+            Err(CredentialProcessError(message)) => assert!(message.contains("timed out")),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn not_signed_in() {
+        assert!(
+            ProcessCredential::new(&profile(None, Some("arn".to_string())))
+                .await
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,119 @@
+use crate::error::Error::*;
+use crate::error::*;
+use crate::profile::Profile;
+use std::process::Command;
+
+/// Variables which can make the child pick credentials other than the ones injected.
+const CONFLICTING_VARS: [&str; 13] = [
+    "AWS_ACCESS_KEY_ID",
+    "AWS_SECRET_ACCESS_KEY",
+    "AWS_SESSION_TOKEN",
+    "AWS_SECURITY_TOKEN",
+    "AWS_CREDENTIAL_EXPIRATION",
+    "AWS_PROFILE",
+    "AWS_DEFAULT_PROFILE",
+    "AWS_ROLE_ARN",
+    "AWS_ROLE_SESSION_NAME",
+    "AWS_WEB_IDENTITY_TOKEN_FILE",
+    "AWS_CONTAINER_CREDENTIALS_RELATIVE_URI",
+    "AWS_CONTAINER_CREDENTIALS_FULL_URI",
+    "AWS_CONTAINER_AUTHORIZATION_TOKEN",
+];
+
+/// Runs the command with the environment of the profile and returns its exit code.
+pub fn run(profile: &Profile, command: &[String]) -> Result<i32> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| CommandExecutionError("no command given".to_string()))?;
+
+    let mut child = Command::new(program);
+    child.args(args);
+    apply_environment(profile, &mut child)?;
+
+    let status = child
+        .status()
+        .map_err(|e| CommandExecutionError(format!("{}: {}", program, e)))?;
+    Ok(exit_code(status))
+}
+
+fn apply_environment(profile: &Profile, command: &mut Command) -> Result<()> {
+    let env = profile.environment()?;
+    for name in CONFLICTING_VARS.iter().chain(env.unset.iter()) {
+        command.env_remove(name);
+    }
+    command.envs(env.exports);
+    Ok(())
+}
+
+#[cfg(unix)]
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|signal| 128 + signal))
+        .unwrap_or(1)
+}
+
+#[cfg(not(unix))]
+fn exit_code(status: std::process::ExitStatus) -> i32 {
+    status.code().unwrap_or(1)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::profile::{Access, AccessKey, Credential};
+    use chrono::{Duration, Utc};
+
+    fn session_profile() -> Profile {
+        Profile {
+            profile_name: "session".to_string(),
+            region: Some("ap-northeast-1".to_string()),
+            access: Some(Access::AccessKey(AccessKey {
+                access_key_id: "BASE".to_string(),
+                secret_access_key: "base".to_string(),
+                mfa_serial: Some("arn:aws:iam::000000000000:mfa/user".to_string()),
+            })),
+            credential: Some(Credential {
+                access_key_id: "ASIA".to_string(),
+                expiration: Utc::now() + Duration::hours(1),
+                secret_access_key: "secret with 'quote'".to_string(),
+                session_token: "token".to_string(),
+            }),
+            ..Profile::default()
+        }
+    }
+
+    fn output(profile: &Profile, script: &str) -> String {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        command.env("AWS_PROFILE", "stale");
+        apply_environment(profile, &mut command).unwrap();
+        String::from_utf8(command.output().unwrap().stdout).unwrap()
+    }
+
+    #[test]
+    fn injects_session_and_scrubs_profile() {
+        assert_eq!(
+            output(
+                &session_profile(),
+                "printf '%s|%s|%s|%s' \"$AWS_ACCESS_KEY_ID\" \"$AWS_SECRET_ACCESS_KEY\" \"$AWS_DEFAULT_REGION\" \"${AWS_PROFILE-unset}\""
+            ),
+            "ASIA|secret with 'quote'|ap-northeast-1|unset"
+        );
+    }
+
+    #[test]
+    fn passes_exit_code() {
+        let command = vec!["sh".to_string(), "-c".to_string(), "exit 3".to_string()];
+        assert_eq!(run(&session_profile(), &command).unwrap(), 3);
+    }
+
+    #[test]
+    fn expired_session_is_not_executed() {
+        let mut profile = session_profile();
+        profile.credential.as_mut().unwrap().expiration = Utc::now() - Duration::hours(1);
+        let command = vec!["true".to_string()];
+        assert!(run(&profile, &command).is_err());
+    }
+}
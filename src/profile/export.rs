@@ -1,34 +1,220 @@
-pub fn rc(exports: Vec<(&str, String)>, unset: Vec<&str>, messages: Vec<&str>) -> String {
+use crate::shell::Shell;
+
+/// Renders lines of a script for one shell dialect.
+trait Dialect {
+    fn export(name: &str, value: &str) -> String;
+    fn unset(name: &str) -> String;
+    fn echo(message: &str) -> String;
+}
+
+pub fn rc(
+    shell: Shell,
+    exports: Vec<(&str, String)>,
+    unset: Vec<&str>,
+    messages: Vec<&str>,
+) -> String {
+    match shell {
+        Shell::Posix | Shell::Bash | Shell::Zsh => render::<posix::Posix>(exports, unset, messages),
+        Shell::Fish => render::<fish::Fish>(exports, unset, messages),
+        Shell::Pwsh => render::<powershell::PowerShell>(exports, unset, messages),
+        Shell::Nushell => render::<nushell::Nushell>(exports, unset, messages),
+        Shell::Csh => render::<csh::Csh>(exports, unset, messages),
+    }
+}
+
+fn render<D: Dialect>(
+    exports: Vec<(&str, String)>,
+    unset: Vec<&str>,
+    messages: Vec<&str>,
+) -> String {
     let mut ret = String::new();
     for (name, value) in exports {
-        ret.push_str(&format!("export {}={}\n", name, value));
+        ret.push_str(&D::export(name, &value));
+        ret.push('\n');
     }
 
     for name in unset {
-        ret.push_str(&format!("unset {}\n", name));
+        ret.push_str(&D::unset(name));
+        ret.push('\n');
     }
 
     for message in messages {
-        ret.push_str(&format!("echo '{}'\n", message));
+        ret.push_str(&D::echo(message));
+        ret.push('\n');
     }
 
     ret
 }
 
+/// sh, bash and zsh. Nothing is special inside single quotes, so only the quote itself needs care.
+mod posix {
+    use super::Dialect;
+
+    pub fn quote(value: &str) -> String {
+        format!("'{}'", value.replace('\'', r"'\''"))
+    }
+
+    pub struct Posix;
+
+    impl Dialect for Posix {
+        fn export(name: &str, value: &str) -> String {
+            format!("export {}={}", name, quote(value))
+        }
+
+        fn unset(name: &str) -> String {
+            format!("unset {}", name)
+        }
+
+        fn echo(message: &str) -> String {
+            format!("echo {}", quote(message))
+        }
+    }
+}
+
+/// fish. Inside single quotes only `\\` and `\'` are escape sequences.
+mod fish {
+    use super::Dialect;
+
+    pub fn quote(value: &str) -> String {
+        format!("'{}'", value.replace('\\', r"\\").replace('\'', r"\'"))
+    }
+
+    pub struct Fish;
+
+    impl Dialect for Fish {
+        fn export(name: &str, value: &str) -> String {
+            format!("set -gx {} {}", name, quote(value))
+        }
+
+        fn unset(name: &str) -> String {
+            format!("set -e {}", name)
+        }
+
+        fn echo(message: &str) -> String {
+            format!("echo {}", quote(message))
+        }
+    }
+}
+
+/// PowerShell. Single quoted strings are verbatim, a quote is escaped by doubling it.
+/// Typographic single quotes are treated as quotes too, so they are doubled as well.
+mod powershell {
+    use super::Dialect;
+
+    pub fn quote(value: &str) -> String {
+        let mut ret = String::from("'");
+        for c in value.chars() {
+            if let '\'' | '\u{2018}' | '\u{2019}' | '\u{201a}' | '\u{201b}' = c {
+                ret.push(c);
+            }
+            ret.push(c);
+        }
+        ret.push('\'');
+        ret
+    }
+
+    pub struct PowerShell;
+
+    impl Dialect for PowerShell {
+        fn export(name: &str, value: &str) -> String {
+            format!("$Env:{} = {}", name, quote(value))
+        }
+
+        fn unset(name: &str) -> String {
+            format!(
+                "Remove-Item -Path Env:{} -ErrorAction SilentlyContinue",
+                name
+            )
+        }
+
+        fn echo(message: &str) -> String {
+            format!("Write-Host {}", quote(message))
+        }
+    }
+}
+
+/// nushell. Single quoted strings can not contain a quote, so double quoted strings are used.
+/// They are not interpolated unless prefixed with `$`.
+mod nushell {
+    use super::Dialect;
+
+    pub fn quote(value: &str) -> String {
+        let mut ret = String::from("\"");
+        for c in value.chars() {
+            match c {
+                '"' => ret.push_str("\\\""),
+                '\\' => ret.push_str("\\\\"),
+                '\n' => ret.push_str("\\n"),
+                '\r' => ret.push_str("\\r"),
+                '\t' => ret.push_str("\\t"),
+                _ => ret.push(c),
+            }
+        }
+        ret.push('"');
+        ret
+    }
+
+    pub struct Nushell;
+
+    impl Dialect for Nushell {
+        fn export(name: &str, value: &str) -> String {
+            format!("$env.{} = {}", name, quote(value))
+        }
+
+        fn unset(name: &str) -> String {
+            format!("hide-env -i {}", name)
+        }
+
+        fn echo(message: &str) -> String {
+            format!("print {}", quote(message))
+        }
+    }
+}
+
+/// csh and tcsh. Same as POSIX single quotes, but history substitution still happens on `!`.
+mod csh {
+    use super::Dialect;
+
+    pub fn quote(value: &str) -> String {
+        format!("'{}'", value.replace('\'', r"'\''").replace('!', r"\!"))
+    }
+
+    pub struct Csh;
+
+    impl Dialect for Csh {
+        fn export(name: &str, value: &str) -> String {
+            format!("setenv {} {}", name, quote(value))
+        }
+
+        fn unset(name: &str) -> String {
+            format!("unsetenv {}", name)
+        }
+
+        fn echo(message: &str) -> String {
+            format!("echo {}", quote(message))
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn sample(shell: Shell) -> String {
+        rc(
+            shell,
+            vec![("ABC", "aaa".to_string()), ("XYZ", "bbb".to_string())],
+            vec!["FOO", "BAR"],
+            vec!["text", "message"],
+        )
+    }
+
     #[test]
     fn test_rc() {
         assert_eq!(
-            rc(
-                vec![("ABC", "aaa".to_string()), ("XYZ", "bbb".to_string())],
-                vec!["FOO", "BAR"],
-                vec!["text", "message"],
-            ),
-            r#"export ABC=aaa
-export XYZ=bbb
+            sample(Shell::Posix),
+            r#"export ABC='aaa'
+export XYZ='bbb'
 unset FOO
 unset BAR
 echo 'text'
@@ -36,4 +222,83 @@ echo 'message'
 "#,
         );
     }
+
+    #[test]
+    fn test_rc_fish() {
+        assert_eq!(
+            sample(Shell::Fish),
+            r#"set -gx ABC 'aaa'
+set -gx XYZ 'bbb'
+set -e FOO
+set -e BAR
+echo 'text'
+echo 'message'
+"#,
+        );
+    }
+
+    #[test]
+    fn test_rc_powershell() {
+        assert_eq!(
+            sample(Shell::Pwsh),
+            r#"$Env:ABC = 'aaa'
+$Env:XYZ = 'bbb'
+Remove-Item -Path Env:FOO -ErrorAction SilentlyContinue
+Remove-Item -Path Env:BAR -ErrorAction SilentlyContinue
+Write-Host 'text'
+Write-Host 'message'
+"#,
+        );
+    }
+
+    #[test]
+    fn test_rc_nushell() {
+        assert_eq!(
+            sample(Shell::Nushell),
+            r#"$env.ABC = "aaa"
+$env.XYZ = "bbb"
+hide-env -i FOO
+hide-env -i BAR
+print "text"
+print "message"
+"#,
+        );
+    }
+
+    #[test]
+    fn test_rc_csh() {
+        assert_eq!(
+            sample(Shell::Csh),
+            r#"setenv ABC 'aaa'
+setenv XYZ 'bbb'
+unsetenv FOO
+unsetenv BAR
+echo 'text'
+echo 'message'
+"#,
+        );
+    }
+
+    const HOSTILE: &str = r#"a b$(rm -rf ~)`x`'q"\!"#;
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(posix::quote(HOSTILE), r#"'a b$(rm -rf ~)`x`'\''q"\!'"#);
+        assert_eq!(fish::quote(HOSTILE), r#"'a b$(rm -rf ~)`x`\'q"\\!'"#);
+        assert_eq!(powershell::quote(HOSTILE), r#"'a b$(rm -rf ~)`x`''q"\!'"#);
+        assert_eq!(nushell::quote(HOSTILE), r#""a b$(rm -rf ~)`x`'q\"\\!""#);
+        assert_eq!(csh::quote(HOSTILE), r#"'a b$(rm -rf ~)`x`'\''q"\\!'"#);
+        assert_eq!(powershell::quote("it\u{2019}s"), "'it\u{2019}\u{2019}s'");
+    }
+
+    #[test]
+    fn test_quote_posix_round_trip() {
+        let output = std::process::Command::new("sh")
+            .arg("-c")
+            .arg(format!("printf %s {}", posix::quote(HOSTILE)))
+            .output();
+        if let Ok(output) = output {
+            assert_eq!(String::from_utf8_lossy(&output.stdout), HOSTILE);
+        }
+    }
 }
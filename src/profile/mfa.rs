@@ -0,0 +1,349 @@
+use crate::client::create_session;
+use crate::client::diagnosis::Cause;
+use crate::error::Error::*;
+use crate::error::*;
+use crate::file::mfa_history::{read_used_tokens, record_used_token, UsedToken};
+use crate::file::mfa_seeds::{read_seeds, seed_store_exists, write_seeds};
+use crate::profile::credential_process::run_command;
+use crate::profile::totp::TotpSeed;
+use crate::profile::{ProfileMap, ProfileType};
+use chrono::{DateTime, Utc};
+use std::io::{BufRead, Write};
+
+/// Time step of TOTP devices in seconds.
+const PERIOD: i64 = 30;
+
+/// Seconds to wait for `mfa_process`, e.g. for the touch of a hardware key.
+const MFA_PROCESS_TIMEOUT: u64 = 60;
+
+/// Where the token comes from when none is given.
+enum TokenSource {
+    /// `mfa_process` of the profile.
+    Process(String),
+    /// stored seed of the device.
+    Seed(TotpSeed),
+    Terminal,
+}
+
+impl TokenSource {
+    fn new(profile_map: &ProfileMap, profile_name: &str, mfa_serial: &str) -> Result<Self> {
+        if let Some(command) = profile_map.mfa_process(profile_name)? {
+            return Ok(TokenSource::Process(command.to_owned()));
+        }
+        Ok(match stored_seed(mfa_serial) {
+            Some(seed) => TokenSource::Seed(seed),
+            None => TokenSource::Terminal,
+        })
+    }
+
+    async fn next(&self, mfa_serial: &str) -> Result<String> {
+        match self {
+            TokenSource::Process(command) => run_mfa_process(command).await,
+            TokenSource::Seed(seed) => seed.code(Utc::now().timestamp()),
+            TokenSource::Terminal => prompt(mfa_serial),
+        }
+    }
+}
+
+/// Signs in to the profile with the token, or one of `mfa_process`, the stored seed or the terminal.
+/// Codes rejected by STS can be retried, and a code used last time waits for the next one.
+pub async fn sign_in(
+    profile_map: &mut ProfileMap,
+    profile_name: &str,
+    mut token: Option<String>,
+) -> Result<()> {
+    let first_hop = profile_map.sign_in_mfa_serial(profile_name)?;
+    let mut source = match &first_hop {
+        // mfa_process is the one of the hop owning the device, not of the profile signed in.
+        Some((hop, mfa_serial)) if token.is_none() => {
+            TokenSource::new(profile_map, hop, mfa_serial)?
+        }
+        _ => TokenSource::Terminal,
+    };
+    let mfa_serial = first_hop.map(|(_, mfa_serial)| mfa_serial);
+    loop {
+        let token = match &mfa_serial {
+            Some(mfa_serial) => Some(fresh_token(mfa_serial, token.take(), &source).await?),
+            None => token.take(),
+        };
+        let result = match profile_map.get(profile_name)?.profile_type() {
+            ProfileType::AssumeRole(_) | ProfileType::CredentialSource(_) => profile_map
+                .sign_in(profile_name, token.clone())
+                .await
+                .map(|_| ()),
+            ProfileType::SessionWithMFA => {
+                let profile = profile_map.get_mut(profile_name)?;
+                create_session::send(profile, token.as_deref().unwrap_or_default()).await
+            }
+            _ => Err(ProfileNotForSignIn(profile_name.to_owned())),
+        };
+
+        match (result, &mfa_serial, token) {
+            (Ok(()), Some(mfa_serial), Some(token)) => {
+                return record_used_token(mfa_serial, &token)
+            }
+            (Err(e), Some(mfa_serial), _) if rejected(&e) => {
+                eprintln!("mfa token for {} was rejected.", mfa_serial);
+                if !confirm("retry with another token? [Y/n] ")? {
+                    return Err(MfaTokenRejected(mfa_serial.to_owned()));
+                }
+                // a generated code which is rejected would be rejected again, so the next one is typed.
+                source = TokenSource::Terminal;
+            }
+            (result, _, _) => return result,
+        }
+    }
+}
+
+/// Token of `mfa_process` for the role, when it is assumed without `in`.
+/// Returns `mfa_serial` of the role too, to record the token after it is used.
+pub async fn process_token(
+    profile_map: &ProfileMap,
+    profile_name: &str,
+) -> Result<Option<(String, String)>> {
+    let mfa_serial = match profile_map.get(profile_name)?.mfa_serial() {
+        Some(mfa_serial) => mfa_serial.to_owned(),
+        None => return Ok(None),
+    };
+    let command = match profile_map.mfa_process(profile_name)? {
+        Some(command) => command.to_owned(),
+        None => return Ok(None),
+    };
+    let token = fresh_token(&mfa_serial, None, &TokenSource::Process(command)).await?;
+    Ok(Some((mfa_serial, token)))
+}
+
+/// A valid token which was not used last time. Waits for the next code if it was.
+async fn fresh_token(
+    mfa_serial: &str,
+    token: Option<String>,
+    source: &TokenSource,
+) -> Result<String> {
+    let next = || source.next(mfa_serial);
+    let mut token = match token {
+        Some(token) if valid(&token) => token,
+        Some(token) => return Err(InvalidMfaToken(token)),
+        None => next().await?,
+    };
+    let used = read_used_tokens().remove(mfa_serial);
+    while let Some(wait) = reuse_wait(used.as_ref(), &token, Utc::now()) {
+        eprintln!(
+            "mfa token {} is already used. waiting {} seconds for the next one.",
+            token, wait
+        );
+        tokio::time::delay_for(std::time::Duration::from_secs(wait)).await;
+        token = next().await?;
+    }
+    Ok(token)
+}
+
+fn valid(token: &str) -> bool {
+    token.len() == 6 && token.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Reads the token without echo, until it is 6 digits.
+fn prompt(mfa_serial: &str) -> Result<String> {
+    loop {
+        let token = read_hidden(&format!("mfa token for {}: ", mfa_serial))?;
+        if valid(&token) {
+            return Ok(token);
+        }
+        eprintln!("mfa token must be 6 digits.");
+    }
+}
+
+/// Runs `mfa_process`, which prints the current code like `ykman oath accounts code -s aws`.
+async fn run_mfa_process(command: &str) -> Result<String> {
+    let output = run_command(command, std::time::Duration::from_secs(MFA_PROCESS_TIMEOUT))
+        .await
+        .map_err(|message| MfaProcessError(format!("`{}` {}", command, message)))?;
+    let token = output.trim();
+    if !valid(token) {
+        return Err(MfaProcessError(format!(
+            "`{}` printed {:?}, not 6 digits.",
+            command, token
+        )));
+    }
+    Ok(token.to_owned())
+}
+
+fn read_hidden(prompt: &str) -> Result<String> {
+    rpassword::read_password_from_tty(Some(prompt))
+        .map(|input| input.trim().to_owned())
+        .map_err(|e| MfaInputError(e.to_string()))
+}
+
+/// Passphrase of the seed store, from `NGYDV_MFA_PASSPHRASE` or the terminal.
+fn passphrase(new: bool) -> Result<String> {
+    if let Ok(passphrase) = std::env::var("NGYDV_MFA_PASSPHRASE") {
+        return Ok(passphrase);
+    }
+    let passphrase = read_hidden("passphrase of mfa seeds: ")?;
+    if new {
+        if passphrase.is_empty() {
+            return Err(MfaSeedStoreError("passphrase is empty.".to_string()));
+        }
+        if read_hidden("passphrase again: ")? != passphrase {
+            return Err(MfaSeedStoreError("passphrases don't match.".to_string()));
+        }
+    }
+    Ok(passphrase)
+}
+
+/// The stored seed, or none to prompt the token when the store can't be opened.
+fn stored_seed(mfa_serial: &str) -> Option<TotpSeed> {
+    if !seed_store_exists() {
+        return None;
+    }
+    match passphrase(false).and_then(|passphrase| read_seeds(&passphrase)) {
+        Ok(mut seeds) => seeds.remove(mfa_serial),
+        Err(e) => {
+            eprintln!("{}", e);
+            None
+        }
+    }
+}
+
+/// Stores the seed of a virtual mfa device, after checking it with the code the device shows.
+pub fn add_seed(mfa_serial: &str, input: Option<String>, check: bool) -> Result<()> {
+    let input = match input {
+        Some(input) => input,
+        None => read_hidden("base32 seed or otpauth:// uri: ")?,
+    };
+    let mut seed = TotpSeed::parse(&input)?;
+    if check {
+        let code = read_hidden("current code of the device: ")?;
+        seed.skew = seed
+            .find_skew(&code, Utc::now().timestamp(), 1)?
+            .ok_or_else(|| MfaSeedError("the code doesn't match the seed.".to_string()))?;
+        if seed.skew != 0 {
+            eprintln!("the device is {} steps off this clock.", seed.skew);
+        }
+    }
+    let passphrase = passphrase(!seed_store_exists())?;
+    let mut seeds = read_seeds(&passphrase)?;
+    seeds.insert(mfa_serial.to_owned(), seed);
+    write_seeds(&seeds, &passphrase)?;
+    eprintln!("seed of {} is stored.", mfa_serial);
+    Ok(())
+}
+
+pub fn remove_seed(mfa_serial: &str) -> Result<()> {
+    let passphrase = passphrase(false)?;
+    let mut seeds = read_seeds(&passphrase)?;
+    if seeds.remove(mfa_serial).is_none() {
+        return Err(MfaSeedStoreError(format!("no seed of {}.", mfa_serial)));
+    }
+    write_seeds(&seeds, &passphrase)?;
+    eprintln!("seed of {} is removed.", mfa_serial);
+    Ok(())
+}
+
+pub fn list_seeds() -> Result<()> {
+    let mut serials: Vec<String> = read_seeds(&passphrase(false)?)?.into_keys().collect();
+    serials.sort();
+    for serial in serials {
+        println!("{}", serial);
+    }
+    Ok(())
+}
+
+fn confirm(question: &str) -> Result<bool> {
+    eprint!("{}", question);
+    std::io::stderr().flush().ok();
+    let mut answer = String::new();
+    let read = std::io::stdin()
+        .lock()
+        .read_line(&mut answer)
+        .map_err(|e| MfaInputError(e.to_string()))?;
+    Ok(read > 0 && !answer.trim().eq_ignore_ascii_case("n"))
+}
+
+/// Seconds until the next time step, if the token is the last used one and STS may still remember it.
+/// A code is accepted in the steps before and after its own, so it is treated as used for 3 steps.
+fn reuse_wait(used: Option<&UsedToken>, token: &str, now: DateTime<Utc>) -> Option<u64> {
+    let used = used.filter(|used| used.token == token)?;
+    if now.timestamp() / PERIOD > used.used_at.timestamp() / PERIOD + 2 {
+        return None;
+    }
+    Some((PERIOD - now.timestamp() % PERIOD) as u64)
+}
+
+/// STS answers wrong or expired codes with AccessDenied of "MultiFactorAuthentication failed".
+fn rejected(error: &Error) -> bool {
+    matches!(error, StsFailed(failure) if failure.cause == Cause::MfaCodeInvalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::diagnosis::{diagnose, StsContext};
+    use chrono::TimeZone;
+    use rusoto_core::request::BufferedHttpResponse;
+    use rusoto_core::RusotoError;
+    use rusoto_sts::{AssumeRoleError, GetSessionTokenError};
+
+    #[test]
+    fn token_format() {
+        assert!(valid("012345"));
+        assert!(!valid("12345"));
+        assert!(!valid("1234567"));
+        assert!(!valid("12a456"));
+        assert!(!valid("１２３４５６"));
+    }
+
+    #[test]
+    fn reused_token() {
+        let used = UsedToken {
+            token: "123456".to_string(),
+            used_at: Utc.timestamp(1_600_000_005, 0),
+        };
+        let at = |seconds: i64| Utc.timestamp(1_600_000_000 + seconds, 0);
+        assert_eq!(reuse_wait(Some(&used), "123456", at(10)), Some(10));
+        assert_eq!(reuse_wait(Some(&used), "123456", at(75)), Some(5));
+        assert_eq!(reuse_wait(Some(&used), "123456", at(80)), None);
+        assert_eq!(reuse_wait(Some(&used), "654321", at(10)), None);
+        assert_eq!(reuse_wait(None, "123456", at(10)), None);
+    }
+
+    #[test]
+    fn rejected_token() {
+        let response = |body: &str| BufferedHttpResponse {
+            status: hyper::StatusCode::FORBIDDEN,
+            body: body.to_owned().into(),
+            headers: Default::default(),
+        };
+        let mfa_failed = "<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code>\
+            <Message>MultiFactorAuthentication failed with invalid MFA one time pass code. </Message>\
+            </Error></ErrorResponse>";
+        let assume_role = |body| {
+            diagnose(
+                RusotoError::<AssumeRoleError>::Unknown(response(body)),
+                &StsContext::default(),
+            )
+        };
+        assert!(rejected(&assume_role(mfa_failed)));
+        assert!(rejected(&diagnose(
+            RusotoError::<GetSessionTokenError>::Unknown(response(mfa_failed)),
+            &StsContext::default(),
+        )));
+        assert!(!rejected(&assume_role(
+            "<Code>AccessDenied</Code><Message>not authorized to perform sts:AssumeRole</Message>"
+        )));
+        assert!(!rejected(&ProfileNotFound("foo".to_string())));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn mfa_process() {
+        assert_eq!(run_mfa_process("echo ' 012345'").await.unwrap(), "012345");
+        assert!(matches!(
+            run_mfa_process("echo 'touch your key' >&2; echo 12345").await,
+            Err(MfaProcessError(_))
+        ));
+        assert!(matches!(
+            run_mfa_process("exit 1").await,
+            Err(MfaProcessError(_))
+        ));
+    }
+}
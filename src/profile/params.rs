@@ -0,0 +1,266 @@
+use crate::env;
+use crate::error::Error::*;
+use crate::error::*;
+use std::collections::HashMap;
+use std::str::FromStr;
+use structopt::StructOpt;
+
+const DEFAULT_ROLE_SESSION_NAME: &str = "session_name";
+const MAX_ROLE_SESSION_NAME_LENGTH: usize = 64;
+
+/// Parameters of AssumeRole and GetSessionToken requests.
+/// Read from config files, and overridden by command line options.
+#[derive(StructOpt, Clone, Debug, Default, PartialEq)]
+pub struct SessionParams {
+    /// session duration in seconds. `duration_seconds` in config.
+    #[structopt(long)]
+    pub duration_seconds: Option<i64>,
+
+    /// role session name shown in CloudTrail. `{user}`, `{hostname}` and `{profile}` are replaced.
+    /// `role_session_name` in config.
+    #[structopt(long)]
+    pub role_session_name: Option<String>,
+
+    /// external id required by the trust policy of the role. `external_id` in config.
+    #[structopt(long)]
+    pub external_id: Option<String>,
+
+    /// session tag as Key=Value. can be repeated. `session_tags = Key=Value,Key2=Value2` in config.
+    #[structopt(long = "tag", number_of_values = 1)]
+    pub tags: Vec<Tag>,
+
+    /// key of transitive session tag. can be repeated. `transitive_tag_keys = Key,Key2` in config.
+    #[structopt(long = "transitive-tag-key", number_of_values = 1)]
+    pub transitive_tag_keys: Vec<String>,
+
+    /// source identity of the role session. `source_identity` in config.
+    #[structopt(long)]
+    pub source_identity: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+
+impl FromStr for Tag {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.find('=') {
+            Some(i) if i > 0 => Ok(Tag {
+                key: s[..i].trim().to_owned(),
+                value: s[i + 1..].trim().to_owned(),
+            }),
+            _ => Err(ConfigFileError(format!(
+                "session tag {:?} is not Key=Value.",
+                s
+            ))),
+        }
+    }
+}
+
+impl SessionParams {
+    pub fn from_props(profile_name: &str, prop: &HashMap<String, String>) -> Result<Self> {
+        Self::resolve(profile_name, prop, &env::lookup)
+    }
+
+    /// `AWS_ROLE_SESSION_NAME` is preferred to `role_session_name` of the profile, as AWS CLI does.
+    /// `--role-session-name` is merged later and overrides both.
+    fn resolve(
+        profile_name: &str,
+        prop: &HashMap<String, String>,
+        env: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<Self> {
+        let list = |key: &str| -> Vec<String> {
+            prop.get(key).map_or(vec![], |value| {
+                value
+                    .split(',')
+                    .map(|s| s.trim().to_owned())
+                    .filter(|s| !s.is_empty())
+                    .collect()
+            })
+        };
+
+        Ok(Self {
+            duration_seconds: match prop.get("duration_seconds") {
+                Some(value) => Some(value.parse().map_err(|_| {
+                    ConfigFileError(format!(
+                        "duration_seconds {:?} of profile {} is not a number.",
+                        value, profile_name
+                    ))
+                })?),
+                None => None,
+            },
+            role_session_name: env("AWS_ROLE_SESSION_NAME")
+                .or_else(|| prop.get("role_session_name").cloned()),
+            external_id: prop.get("external_id").cloned(),
+            tags: list("session_tags")
+                .iter()
+                .map(|tag| tag.parse())
+                .collect::<Result<Vec<Tag>>>()?,
+            transitive_tag_keys: list("transitive_tag_keys"),
+            source_identity: prop.get("source_identity").cloned(),
+        })
+    }
+
+    /// Overrides with the values given in `other`.
+    pub fn merge(&mut self, other: SessionParams) {
+        if other.duration_seconds.is_some() {
+            self.duration_seconds = other.duration_seconds;
+        }
+        if other.role_session_name.is_some() {
+            self.role_session_name = other.role_session_name;
+        }
+        if other.external_id.is_some() {
+            self.external_id = other.external_id;
+        }
+        if !other.tags.is_empty() {
+            self.tags = other.tags;
+        }
+        if !other.transitive_tag_keys.is_empty() {
+            self.transitive_tag_keys = other.transitive_tag_keys;
+        }
+        if other.source_identity.is_some() {
+            self.source_identity = other.source_identity;
+        }
+    }
+
+    /// Role session name with the template replaced.
+    pub fn role_session_name(&self, profile_name: &str) -> String {
+        let template = self
+            .role_session_name
+            .clone()
+            .unwrap_or_else(|| DEFAULT_ROLE_SESSION_NAME.to_string());
+        expand_role_session_name(&template, &user(), &hostname(), profile_name)
+    }
+}
+
+fn user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
+
+fn hostname() -> String {
+    hostname::get()
+        .ok()
+        .and_then(|name| name.into_string().ok())
+        .map(|name| name.split('.').next().unwrap_or("").to_owned())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// Replaces the template and the characters not allowed in role session name.
+fn expand_role_session_name(template: &str, user: &str, hostname: &str, profile: &str) -> String {
+    template
+        .replace("{user}", user)
+        .replace("{hostname}", hostname)
+        .replace("{profile}", profile)
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "+=,.@_-".contains(c) {
+                c
+            } else {
+                '-'
+            }
+        })
+        .take(MAX_ROLE_SESSION_NAME_LENGTH)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn props(kvs: Vec<(&str, &str)>) -> HashMap<String, String> {
+        kvs.into_iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn from_props() {
+        let params = SessionParams::from_props(
+            "foo",
+            &props(vec![
+                ("duration_seconds", "3600"),
+                ("role_session_name", "{user}-{hostname}"),
+                ("external_id", "ext"),
+                ("session_tags", "Project=ngydv, Team = infra"),
+                ("transitive_tag_keys", "Project,"),
+                ("source_identity", "me"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(params.duration_seconds, Some(3600));
+        assert_eq!(
+            params.tags,
+            vec![
+                Tag {
+                    key: "Project".to_string(),
+                    value: "ngydv".to_string()
+                },
+                Tag {
+                    key: "Team".to_string(),
+                    value: "infra".to_string()
+                },
+            ]
+        );
+        assert_eq!(params.transitive_tag_keys, vec!["Project".to_string()]);
+        assert_eq!(params.source_identity, Some("me".to_string()));
+    }
+
+    #[test]
+    fn role_session_name_from_env() {
+        let config = props(vec![("role_session_name", "{user}")]);
+        let env = env::fake(vec![("AWS_ROLE_SESSION_NAME", "ci")]);
+        let mut params = SessionParams::resolve("foo", &config, &env).unwrap();
+        assert_eq!(params.role_session_name, Some("ci".to_string()));
+        let params_without_env = SessionParams::resolve("foo", &config, &|_| None).unwrap();
+        assert_eq!(
+            params_without_env.role_session_name,
+            Some("{user}".to_string())
+        );
+        params.merge(SessionParams {
+            role_session_name: Some("cli".to_string()),
+            ..SessionParams::default()
+        });
+        assert_eq!(params.role_session_name("foo"), "cli");
+    }
+
+    #[test]
+    fn invalid_props() {
+        assert!(
+            SessionParams::from_props("foo", &props(vec![("duration_seconds", "1h")])).is_err()
+        );
+        assert!(SessionParams::from_props("foo", &props(vec![("session_tags", "=x")])).is_err());
+    }
+
+    #[test]
+    fn merge() {
+        let mut params = SessionParams {
+            duration_seconds: Some(3600),
+            external_id: Some("ext".to_string()),
+            ..SessionParams::default()
+        };
+        params.merge(SessionParams {
+            duration_seconds: Some(900),
+            ..SessionParams::default()
+        });
+        assert_eq!(params.duration_seconds, Some(900));
+        assert_eq!(params.external_id, Some("ext".to_string()));
+    }
+
+    #[test]
+    fn role_session_name_template() {
+        assert_eq!(
+            expand_role_session_name("{user}-{hostname}/{profile}", "k2da", "my host", "adm"),
+            "k2da-my-host-adm"
+        );
+        assert_eq!(
+            expand_role_session_name(&"x".repeat(70), "", "", "").len(),
+            MAX_ROLE_SESSION_NAME_LENGTH
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use super::{Credential, ACTIVE_COLOUR, INACTIVE_COLOUR};
+use crate::env::{self, EnvVariables};
+use crate::error::*;
+use crate::file::credentials::read_saved_credentials;
+use crate::shell::Shell;
+use ansi_term::Color;
+use chrono::Duration;
+use std::collections::HashMap;
+
+/// Prints active profile and its remaining session lifetime for the prompt.
+/// Only the environment variables and the stored sessions are read, aws config files are not.
+pub fn print_prompt(shell: Shell, colour: bool) -> Result<()> {
+    // a broken credentials file is reported by other commands, not at every prompt.
+    let saved = read_saved_credentials().unwrap_or_default();
+    if let Some(segment) = segment(&env::env_vars(), &saved, shell, colour) {
+        print!("{}", segment);
+    }
+    Ok(())
+}
+
+fn segment(
+    env_vars: &EnvVariables,
+    saved: &HashMap<String, Credential>,
+    shell: Shell,
+    colour: bool,
+) -> Option<String> {
+    if let (Some(access_key_id), Some(secret_access_key)) =
+        (&env_vars.aws_access_key_id, &env_vars.aws_secret_access_key)
+    {
+        let found = saved.iter().find(|(_, cred)| {
+            &cred.access_key_id == access_key_id && &cred.secret_access_key == secret_access_key
+        });
+        if let Some((profile_name, cred)) = found {
+            let (text, color) = if cred.alive() {
+                (short_duration(cred.life()), ACTIVE_COLOUR)
+            } else {
+                ("EXPIRED".to_string(), INACTIVE_COLOUR)
+            };
+            let text = if colour {
+                paint(shell, color, &text)
+            } else {
+                text
+            };
+            return Some(format!("{} {}", profile_name, text));
+        }
+    }
+
+    env_vars.aws_profile.clone()
+}
+
+/// Escape sequences have to be marked as zero width, or the shell miscalculates prompt width.
+fn paint(shell: Shell, color: Color, text: &str) -> String {
+    let (start, end) = match shell {
+        Shell::Bash => ("\u{1}", "\u{2}"),
+        Shell::Zsh => ("%{", "%}"),
+        _ => ("", ""),
+    };
+    format!(
+        "{start}{}{end}{}{start}{}{end}",
+        color.prefix(),
+        text,
+        color.suffix(),
+        start = start,
+        end = end,
+    )
+}
+
+fn short_duration(life: Duration) -> String {
+    if life.num_hours() > 0 {
+        format!("{}h{}m", life.num_hours(), life.num_minutes() % 60)
+    } else if life.num_minutes() > 0 {
+        format!("{}m", life.num_minutes())
+    } else {
+        format!("{}s", life.num_seconds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    fn env_vars(key: Option<&str>, profile: Option<&str>) -> EnvVariables {
+        EnvVariables {
+            aws_access_key_id: key.map(|s| s.to_string()),
+            aws_ca_bundle: None,
+            aws_config_file: None,
+            aws_default_output: None,
+            aws_default_region: None,
+            aws_max_attempts: None,
+            aws_pager: None,
+            aws_profile: profile.map(|s| s.to_string()),
+            aws_retry_mode: None,
+            aws_role_session_name: None,
+            aws_secret_access_key: key.map(|s| format!("{}-secret", s)),
+            aws_session_token: None,
+            aws_shared_credentials_file: None,
+        }
+    }
+
+    fn saved() -> HashMap<String, Credential> {
+        let credential = |key: &str, life: Duration| Credential {
+            access_key_id: key.to_string(),
+            expiration: Utc::now() + life,
+            secret_access_key: format!("{}-secret", key),
+            session_token: "token".to_string(),
+        };
+        let mut saved = HashMap::new();
+        saved.insert(
+            "hpmadm".to_string(),
+            credential("ALIVE", Duration::minutes(133) + Duration::seconds(30)),
+        );
+        saved.insert(
+            "hpm".to_string(),
+            credential("EXPIRED", Duration::minutes(-1)),
+        );
+        saved
+    }
+
+    #[test]
+    fn alive_session() {
+        assert_eq!(
+            segment(&env_vars(Some("ALIVE"), None), &saved(), Shell::Bash, false),
+            Some("hpmadm 2h13m".to_string())
+        );
+    }
+
+    #[test]
+    fn expired_session() {
+        assert_eq!(
+            segment(&env_vars(Some("EXPIRED"), None), &saved(), Shell::Zsh, true),
+            Some(format!(
+                "hpm %{{{}%}}EXPIRED%{{{}%}}",
+                INACTIVE_COLOUR.prefix(),
+                INACTIVE_COLOUR.suffix()
+            ))
+        );
+    }
+
+    #[test]
+    fn profile_or_nothing() {
+        assert_eq!(
+            segment(&env_vars(None, Some("plain")), &saved(), Shell::Fish, true),
+            Some("plain".to_string())
+        );
+        assert_eq!(
+            segment(&env_vars(None, None), &saved(), Shell::Fish, true),
+            None
+        );
+    }
+
+    #[test]
+    fn short_durations() {
+        assert_eq!(short_duration(Duration::minutes(60)), "1h0m");
+        assert_eq!(short_duration(Duration::seconds(125)), "2m");
+        assert_eq!(short_duration(Duration::seconds(5)), "5s");
+    }
+}
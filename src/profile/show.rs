@@ -24,7 +24,7 @@ pub fn show_current_profile(profile_map: &ProfileMap) {
     }
 
     println!("3. checking profile named 'default'.");
-    if let Ok(_) = profile_map.get("default") {
+    if profile_map.get("default").is_ok() {
         println!("\nuse profile \"default\" as default.");
         return
     } else {
@@ -0,0 +1,229 @@
+use crate::error::Error::*;
+use crate::error::*;
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac, NewMac};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+use std::str::FromStr;
+use url::Url;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+pub enum Algorithm {
+    SHA1,
+    SHA256,
+    SHA512,
+}
+
+impl FromStr for Algorithm {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_uppercase().as_str() {
+            "SHA1" => Ok(Algorithm::SHA1),
+            "SHA256" => Ok(Algorithm::SHA256),
+            "SHA512" => Ok(Algorithm::SHA512),
+            _ => Err(MfaSeedError(format!("algorithm {:?} is not supported.", s))),
+        }
+    }
+}
+
+/// Seed of a virtual mfa device, to compute the codes of RFC 6238.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct TotpSeed {
+    /// the secret in base32 without padding.
+    pub secret: String,
+    pub algorithm: Algorithm,
+    pub digits: u32,
+    pub period: u64,
+    /// time steps the device is ahead of this clock, found when the seed is added.
+    #[serde(default)]
+    pub skew: i64,
+}
+
+impl TotpSeed {
+    /// Reads a base32 secret, or an `otpauth://totp/...` uri from a QR code.
+    pub fn parse(input: &str) -> Result<Self> {
+        let input = input.trim();
+        let mut seed = TotpSeed {
+            secret: String::new(),
+            algorithm: Algorithm::SHA1,
+            digits: 6,
+            period: 30,
+            skew: 0,
+        };
+        if input.starts_with("otpauth://") {
+            let uri = Url::parse(input).map_err(|e| MfaSeedError(e.to_string()))?;
+            if uri.host_str() != Some("totp") {
+                return Err(MfaSeedError(
+                    "only otpauth://totp/ is supported.".to_string(),
+                ));
+            }
+            for (key, value) in uri.query_pairs() {
+                match key.as_ref() {
+                    "secret" => seed.secret = value.into_owned(),
+                    "algorithm" => seed.algorithm = value.parse()?,
+                    "digits" => seed.digits = parse_number(&key, &value)?,
+                    "period" => seed.period = parse_number(&key, &value)?,
+                    _ => (),
+                }
+            }
+        } else {
+            seed.secret = input.to_owned();
+        }
+        seed.secret = normalize_base32(&seed.secret);
+        seed.key()?;
+        // STS accepts only 6 digit codes.
+        if seed.digits != 6 || seed.period == 0 {
+            return Err(MfaSeedError(format!(
+                "{} digits every {} seconds is not supported.",
+                seed.digits, seed.period
+            )));
+        }
+        Ok(seed)
+    }
+
+    fn key(&self) -> Result<Vec<u8>> {
+        match BASE32_NOPAD.decode(self.secret.as_bytes()) {
+            Ok(key) if !key.is_empty() => Ok(key),
+            _ => Err(MfaSeedError("secret is not base32.".to_string())),
+        }
+    }
+
+    /// Time step of the device at the unix time.
+    pub fn step(&self, unix_time: i64) -> i64 {
+        unix_time.div_euclid(self.period as i64) + self.skew
+    }
+
+    /// The code of the device at the unix time.
+    pub fn code(&self, unix_time: i64) -> Result<String> {
+        self.code_at_step(self.step(unix_time))
+    }
+
+    /// HOTP of RFC 4226 with the time step as the counter.
+    pub fn code_at_step(&self, step: i64) -> Result<String> {
+        let key = self.key()?;
+        let counter = (step as u64).to_be_bytes();
+        let hash = match self.algorithm {
+            Algorithm::SHA1 => hmac::<Hmac<Sha1>>(&key, &counter),
+            Algorithm::SHA256 => hmac::<Hmac<Sha256>>(&key, &counter),
+            Algorithm::SHA512 => hmac::<Hmac<Sha512>>(&key, &counter),
+        };
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+        Ok(format!(
+            "{:0width$}",
+            binary % 10u32.pow(self.digits),
+            width = self.digits as usize
+        ))
+    }
+
+    /// Finds the drift of the device from the code it shows, within `window` steps of this clock.
+    pub fn find_skew(&self, code: &str, unix_time: i64, window: i64) -> Result<Option<i64>> {
+        let step = unix_time.div_euclid(self.period as i64);
+        for skew in std::iter::once(0).chain((1..=window).flat_map(|n| vec![-n, n])) {
+            if self.code_at_step(step + skew)? == code {
+                return Ok(Some(skew));
+            }
+        }
+        Ok(None)
+    }
+}
+
+fn hmac<M: Mac + NewMac>(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut mac = M::new_varkey(key).expect("hmac accepts keys of any length");
+    mac.update(message);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn parse_number<T: FromStr>(key: &str, value: &str) -> Result<T> {
+    value
+        .parse()
+        .map_err(|_| MfaSeedError(format!("{} {:?} is not a number.", key, value)))
+}
+
+/// Authenticator apps show the secret in lowercase groups, with or without padding.
+fn normalize_base32(secret: &str) -> String {
+    secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '-' && *c != '=')
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed(key: &[u8], algorithm: Algorithm) -> TotpSeed {
+        TotpSeed {
+            secret: BASE32_NOPAD.encode(key),
+            algorithm,
+            digits: 8,
+            period: 30,
+            skew: 0,
+        }
+    }
+
+    #[test]
+    fn rfc6238_vectors() {
+        let sha1 = seed(b"12345678901234567890", Algorithm::SHA1);
+        let sha256 = seed(b"12345678901234567890123456789012", Algorithm::SHA256);
+        let sha512 = seed(
+            b"1234567890123456789012345678901234567890123456789012345678901234",
+            Algorithm::SHA512,
+        );
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, code1, code256, code512) in vectors.iter() {
+            assert_eq!(sha1.code(*time).unwrap(), *code1);
+            assert_eq!(sha256.code(*time).unwrap(), *code256);
+            assert_eq!(sha512.code(*time).unwrap(), *code512);
+        }
+    }
+
+    #[test]
+    fn parse_seed() {
+        let plain = TotpSeed::parse("gezd gnbv gy3t qojq gezd gnbv gy3t qojq").unwrap();
+        assert_eq!(plain.secret, "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ");
+        assert_eq!(plain.code(59).unwrap(), "287082");
+
+        let uri = TotpSeed::parse(
+            "otpauth://totp/Amazon%20Web%20Services:user@000000000000?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ&issuer=Amazon%20Web%20Services&digits=6&algorithm=SHA256&period=60",
+        )
+        .unwrap();
+        assert_eq!(uri.digits, 6);
+        assert_eq!(uri.period, 60);
+        assert_eq!(uri.algorithm, Algorithm::SHA256);
+
+        assert!(TotpSeed::parse("not base32!").is_err());
+        assert!(TotpSeed::parse("otpauth://hotp/x?secret=GEZDGNBV").is_err());
+        assert!(TotpSeed::parse("otpauth://totp/x?secret=GEZDGNBV&digits=4").is_err());
+        assert!(TotpSeed::parse("otpauth://totp/x?secret=GEZDGNBV&digits=8").is_err());
+    }
+
+    #[test]
+    fn clock_drift() {
+        let mut seed = TotpSeed::parse("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        let ahead = seed.code(1111111111 + 30).unwrap();
+        assert_eq!(seed.find_skew(&ahead, 1111111111, 1).unwrap(), Some(1));
+        let behind = seed.code(1111111111 - 30).unwrap();
+        assert_eq!(seed.find_skew(&behind, 1111111111, 1).unwrap(), Some(-1));
+        let far = seed.code(1111111111 + 90).unwrap();
+        assert_eq!(seed.find_skew(&far, 1111111111, 1).unwrap(), None);
+
+        seed.skew = 1;
+        assert_eq!(seed.code(1111111111).unwrap(), ahead);
+    }
+}
@@ -0,0 +1,185 @@
+use crate::client::assume_role;
+use crate::error::Error::*;
+use crate::error::*;
+use crate::profile::ProfileMap;
+use futures::future::join_all;
+
+/// Assumes the roles chained from the signed-in profile, concurrently for the roles of the same hop,
+/// and prints the results to stderr. The sessions are kept in `profile_map` to be stored once.
+pub async fn dependents(profile_map: &mut ProfileMap, profile_name: &str) -> Result<()> {
+    let groups = profile_map.dependents(profile_name)?;
+    if groups.is_empty() {
+        eprintln!("no roles are chained from {}.", profile_name);
+        return Ok(());
+    }
+    let mut results: Vec<(String, std::result::Result<String, String>)> = vec![];
+    for group in groups {
+        let (names, skipped): (Vec<String>, Vec<String>) = group.into_iter().partition(|name| {
+            !results.iter().any(|(failed, result)| {
+                result.is_err()
+                    && profile_map
+                        .source_chain(name)
+                        .is_ok_and(|c| c.contains(failed))
+            })
+        });
+        let map = &*profile_map;
+        let assumed = join_all(
+            names
+                .iter()
+                .map(|name| async move { assume_role::assume(name, map, &mut None).await }),
+        )
+        .await;
+        for (name, result) in names.into_iter().zip(assumed) {
+            let result = match result {
+                Ok(credential) => {
+                    let life = credential.life_str();
+                    profile_map.get_mut(&name)?.credential = Some(credential);
+                    Ok(format!("valid for{}", life))
+                }
+                Err(e) => Err(e.to_string()),
+            };
+            results.push((name, result));
+        }
+        for name in skipped {
+            results.push((name, Err("the source was not assumed.".to_string())));
+        }
+    }
+
+    let width = results
+        .iter()
+        .map(|(name, _)| name.len())
+        .max()
+        .unwrap_or(0);
+    let mut failures = 0;
+    for (name, result) in results.iter() {
+        match result {
+            Ok(message) => eprintln!("{:width$}  ok      {}", name, message, width = width),
+            Err(message) => {
+                failures += 1;
+                eprintln!("{:width$}  failed  {}", name, message, width = width)
+            }
+        }
+    }
+    eprintln!(
+        "{} of {} roles chained from {} assumed.",
+        results.len() - failures,
+        results.len(),
+        profile_name
+    );
+    if failures > 0 {
+        return Err(DependentsFailed(failures, results.len()));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::util::stand_in;
+    use crate::profile::{Access, AccessKey, AssumedRole, Credential, Profile, StsEndpoint};
+    use chrono::{Duration, Utc};
+    use hyper::http::request::Parts;
+    use hyper::StatusCode;
+
+    /// Denies the role named `broken`, and answers the others with a session named after the role.
+    fn sts(_: Parts, body: String) -> (StatusCode, String) {
+        let role = body
+            .split('&')
+            .find_map(|param| param.strip_prefix("RoleArn="))
+            .and_then(|arn| arn.rsplit("%2F").next())
+            .unwrap_or_default()
+            .to_string();
+        if role == "broken" {
+            return (
+                StatusCode::FORBIDDEN,
+                "<ErrorResponse><Error><Type>Sender</Type><Code>AccessDenied</Code><Message>denied</Message></Error></ErrorResponse>".to_string(),
+            );
+        }
+        (
+            StatusCode::OK,
+            format!(
+                r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+  <AssumeRoleResult>
+    <Credentials>
+      <AccessKeyId>ASIA{}</AccessKeyId>
+      <SecretAccessKey>secret</SecretAccessKey>
+      <SessionToken>token</SessionToken>
+      <Expiration>2999-01-01T00:00:00Z</Expiration>
+    </Credentials>
+  </AssumeRoleResult>
+  <ResponseMetadata>
+    <RequestId>00000000-0000-0000-0000-000000000000</RequestId>
+  </ResponseMetadata>
+</AssumeRoleResponse>"#,
+                role
+            ),
+        )
+    }
+
+    #[tokio::test]
+    async fn assumes_dependents() {
+        let url = stand_in(sts);
+        let profile = |name: &str, access: Access| Profile {
+            profile_name: name.to_string(),
+            access: Some(access),
+            sts_endpoint: StsEndpoint {
+                override_url: Some(url.clone()),
+                ..StsEndpoint::default()
+            },
+            ..Profile::default()
+        };
+        let role = |name: &str, source: &str| {
+            profile(
+                name,
+                Access::AssumeRole(AssumedRole {
+                    role_arn: format!("arn:aws:iam::000000000000:role/{}", name)
+                        .parse()
+                        .unwrap(),
+                    mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+                    source_profile: source.to_string(),
+                }),
+            )
+        };
+        let mut profile_map = ProfileMap::new();
+        profile_map.insert(
+            "base",
+            Profile {
+                credential: Some(Credential {
+                    access_key_id: "ASIABASE".to_string(),
+                    expiration: Utc::now() + Duration::hours(1),
+                    secret_access_key: "secret".to_string(),
+                    session_token: "token".to_string(),
+                }),
+                ..profile("base", Access::AccessKey(AccessKey::default()))
+            },
+        );
+        for (name, source) in [
+            ("dev", "base"),
+            ("prod", "base"),
+            ("broken", "base"),
+            ("admin", "dev"),
+            ("child", "broken"),
+        ] {
+            profile_map.insert(name, role(name, source));
+        }
+
+        match dependents(&mut profile_map, "base").await {
+            Err(DependentsFailed(2, 5)) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        for name in ["dev", "prod", "admin"] {
+            assert_eq!(
+                profile_map
+                    .get(name)
+                    .unwrap()
+                    .credential
+                    .as_ref()
+                    .unwrap()
+                    .access_key_id,
+                format!("ASIA{}", name)
+            );
+        }
+        assert!(profile_map.get("broken").unwrap().credential.is_none());
+        assert!(profile_map.get("child").unwrap().credential.is_none());
+    }
+}
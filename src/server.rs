@@ -0,0 +1,109 @@
+pub mod daemon;
+pub mod ecs;
+pub mod imds;
+
+use crate::error::Error::*;
+use crate::error::*;
+use crate::file::credentials::store_credentials;
+use crate::profile::credential_process::ProcessCredential;
+use crate::profile::ProfileMap;
+use chrono::{Duration, Local};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use std::convert::Infallible;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Session of a profile shared by the requests to a local credentials server.
+pub struct Session {
+    profile_map: Mutex<ProfileMap>,
+    pub profile_name: String,
+    margin: Duration,
+}
+
+impl Session {
+    pub fn new(profile_map: ProfileMap, profile_name: &str, margin: Duration) -> Self {
+        Self {
+            profile_map: Mutex::new(profile_map),
+            profile_name: profile_name.to_owned(),
+            margin,
+        }
+    }
+
+    /// Returns credential of the profile, re-assuming role if it expires within the margin.
+    pub async fn credential(&self) -> Result<ProcessCredential> {
+        let mut profile_map = self.profile_map.lock().await;
+        let before = profile_map.get(&self.profile_name)?.credential.clone();
+        let profile = profile_map.refresh(&self.profile_name, self.margin).await?;
+        let credential = ProcessCredential::new(profile).await?;
+        if before != profile.credential {
+            log(&format!(
+                "re-assumed role for profile '{}', expires at {}.",
+                self.profile_name,
+                credential.expiration.as_deref().unwrap_or("-")
+            ));
+            store_credentials(&profile_map)?;
+        }
+        Ok(credential)
+    }
+}
+
+pub fn log(message: &str) {
+    eprintln!("[{}] {}", Local::now().format("%Y-%m-%d %H:%M:%S"), message);
+}
+
+pub fn random_token() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .collect()
+}
+
+pub fn response(status: StatusCode, body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    *response.status_mut() = status;
+    response
+}
+
+pub fn error_response(error: &Error) -> Response<Body> {
+    log(&format!("error: {}", error));
+    response(StatusCode::INTERNAL_SERVER_ERROR, error.to_string())
+}
+
+/// Binds to the address and serves until Ctrl-C. `on_bind` is called with the bound address.
+pub async fn run<H, F>(
+    address: SocketAddr,
+    state: Arc<H>,
+    handle: fn(Arc<H>, Request<Body>) -> F,
+    on_bind: impl FnOnce(SocketAddr),
+) -> Result<()>
+where
+    H: Send + Sync + 'static,
+    F: Future<Output = Response<Body>> + Send + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let response = handle(state.clone(), request);
+                async move { Ok::<_, Infallible>(response.await) }
+            }))
+        }
+    });
+
+    let server = Server::try_bind(&address)
+        .map_err(|e| ServerError(format!("{}: {}", address, e)))?
+        .serve(make_service);
+    on_bind(server.local_addr());
+
+    server
+        .with_graceful_shutdown(async {
+            tokio::signal::ctrl_c().await.ok();
+        })
+        .await
+        .map_err(|e| ServerError(e.to_string()))
+}
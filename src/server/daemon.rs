@@ -0,0 +1,447 @@
+use super::*;
+use crate::file::credentials::{read_saved_credentials, write_saved_credentials};
+use crate::file::{credentials_path, daemon_socket_path};
+use crate::profile::{Access, Credential};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Interval to check the credentials file and the sessions.
+const WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Seconds to wait before re-assuming a role again after a failure.
+const RETRY_AFTER: i64 = 60;
+
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RefreshState {
+    /// the session is alive beyond the margin.
+    Fresh,
+    /// the source session is expired, and the role needs mfa token. Sign in with `ngydv in`.
+    WaitingForSignIn,
+    Failed,
+}
+
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct ProfileStatus {
+    pub profile: String,
+    pub state: RefreshState,
+    pub expiration: Option<String>,
+    pub refreshed_at: Option<String>,
+    pub error: Option<String>,
+}
+
+/// Status written to the clients of the unix socket, as json.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started_at: String,
+    /// minutes before expiration to re-assume roles.
+    pub refresh_before: i64,
+    pub profiles: Vec<ProfileStatus>,
+}
+
+impl DaemonStatus {
+    /// Whether the daemon keeps the session of the profile warm.
+    pub fn refreshes(&self, profile_name: &str) -> bool {
+        self.profiles.iter().any(|p| p.profile == profile_name)
+    }
+}
+
+/// Re-assumes the role profiles `margin` before their sessions expire, while the sessions they are
+/// assumed from are alive. Sessions signed in by other commands are read from the credentials file.
+pub async fn run(
+    mut profile_map: ProfileMap,
+    profile_names: Vec<String>,
+    margin: Duration,
+) -> Result<()> {
+    for name in profile_names.iter() {
+        match profile_map.get(name)?.access {
+            Some(Access::AssumeRole(_)) | Some(Access::CredentialSource(_)) => (),
+            _ => return Err(ProfileTypeError(name.to_owned(), "assume role".to_string())),
+        }
+    }
+    let status = Arc::new(Mutex::new(DaemonStatus {
+        pid: std::process::id(),
+        started_at: Utc::now().to_rfc3339(),
+        refresh_before: margin.num_minutes(),
+        profiles: profile_names
+            .iter()
+            .map(|name| ProfileStatus {
+                profile: name.to_owned(),
+                state: RefreshState::Fresh,
+                expiration: None,
+                refreshed_at: None,
+                error: None,
+            })
+            .collect(),
+    }));
+
+    let socket = daemon_socket_path()?;
+    let listener = listen(&socket)?;
+    tokio::spawn(serve_status(listener, status.clone()));
+    log(&format!(
+        "refreshing {} {} minutes before expiration. status at {}",
+        profile_names.join(", "),
+        margin.num_minutes(),
+        socket.display()
+    ));
+
+    let result = tokio::select! {
+        result = watch(&mut profile_map, margin, &status) => result,
+        _ = stopped() => Ok(()),
+    };
+    std::fs::remove_file(&socket).ok();
+    log("stopped.");
+    result
+}
+
+/// Status of the running daemon, or `None` if no daemon answers on the socket.
+#[cfg(unix)]
+pub fn status() -> Option<DaemonStatus> {
+    use std::io::Read;
+    let mut stream = std::os::unix::net::UnixStream::connect(daemon_socket_path().ok()?).ok()?;
+    stream
+        .set_read_timeout(Some(std::time::Duration::from_secs(1)))
+        .ok()?;
+    let mut body = String::new();
+    stream.read_to_string(&mut body).ok()?;
+    serde_json::from_str(&body).ok()
+}
+
+#[cfg(not(unix))]
+pub fn status() -> Option<DaemonStatus> {
+    None
+}
+
+async fn watch(
+    profile_map: &mut ProfileMap,
+    margin: Duration,
+    status: &Mutex<DaemonStatus>,
+) -> Result<()> {
+    let path = credentials_path()?;
+    let mut modified = None;
+    loop {
+        let current = modified_time(&path);
+        if current != modified {
+            if modified.is_some() {
+                log("credentials file changed.");
+            }
+            match read_saved_credentials() {
+                Ok(saved) => {
+                    load_sessions(profile_map, &saved);
+                    modified = current;
+                }
+                Err(e) => {
+                    log(&e.to_string());
+                    tokio::time::delay_for(WATCH_INTERVAL).await;
+                    continue;
+                }
+            }
+        }
+
+        // the status is answered during STS requests.
+        let mut profiles = status.lock().await.profiles.clone();
+        let before = sessions(profile_map);
+        let mut refreshed = false;
+        for profile_status in profiles.iter_mut() {
+            refreshed |= refresh(profile_map, margin, profile_status).await;
+        }
+        status.lock().await.profiles = profiles;
+        if refreshed {
+            // the roles chained through are re-assumed too.
+            let mut refreshed = sessions(profile_map);
+            refreshed.retain(|name, session| before.get(name) != Some(session));
+            if modified.is_some() && !path.exists() {
+                // `clear session` removed the file, so the sessions are not written back.
+                log("credentials file removed. sessions are dropped.");
+                load_sessions(profile_map, &HashMap::new());
+                modified = None;
+            } else {
+                let saved = save_sessions(&refreshed)?;
+                load_sessions(profile_map, &saved);
+                modified = modified_time(&path);
+            }
+        }
+        tokio::time::delay_for(WATCH_INTERVAL).await;
+    }
+}
+
+/// Replaces the sessions with the saved ones, dropping those removed from the file.
+fn load_sessions(profile_map: &mut ProfileMap, saved: &HashMap<String, Credential>) {
+    for (name, profile) in profile_map.iter_mut() {
+        profile.credential = saved.get(name).cloned();
+    }
+}
+
+fn sessions(profile_map: &ProfileMap) -> HashMap<String, Credential> {
+    profile_map
+        .profiles()
+        .into_iter()
+        .filter_map(|p| Some((p.profile_name.clone(), p.credential.clone()?)))
+        .collect()
+}
+
+/// Writes the refreshed sessions into the file as it is now, keeping the sessions which other
+/// commands signed in meanwhile. Returns all the sessions written.
+fn save_sessions(refreshed: &HashMap<String, Credential>) -> Result<HashMap<String, Credential>> {
+    let mut saved = read_saved_credentials()?;
+    saved.extend(refreshed.iter().map(|(k, v)| (k.clone(), v.clone())));
+    write_saved_credentials(&saved)?;
+    Ok(saved)
+}
+
+/// Re-assumes the role if needed, and returns whether its session is re-issued.
+async fn refresh(
+    profile_map: &mut ProfileMap,
+    margin: Duration,
+    status: &mut ProfileStatus,
+) -> bool {
+    let name = status.profile.clone();
+    let before = match profile_map.get(&name) {
+        Ok(profile) if !profile.needs_refresh(margin) => {
+            status.state = RefreshState::Fresh;
+            status.expiration = profile
+                .credential
+                .as_ref()
+                .map(|c| c.expiration.to_rfc3339());
+            return false;
+        }
+        Ok(profile) => profile.credential.clone(),
+        Err(_) => return false,
+    };
+    if !profile_map.assumable(&name).unwrap_or(false) {
+        if status.state != RefreshState::WaitingForSignIn {
+            log(&format!(
+                "profile '{}' waits for sign in. the session it is assumed from is expired.",
+                name
+            ));
+            status.state = RefreshState::WaitingForSignIn;
+        }
+        return false;
+    }
+    let retrying = status.state == RefreshState::Failed
+        && status.refreshed_at.as_deref().is_some_and(|at| {
+            chrono::DateTime::parse_from_rfc3339(at)
+                .is_ok_and(|at| Utc::now() < at + Duration::seconds(RETRY_AFTER))
+        });
+    if retrying {
+        return false;
+    }
+
+    status.refreshed_at = Some(Utc::now().to_rfc3339());
+    match profile_map.refresh(&name, margin).await {
+        Ok(profile) if profile.credential != before => {
+            let credential = profile.credential.as_ref();
+            status.state = RefreshState::Fresh;
+            status.expiration = credential.map(|c| c.expiration.to_rfc3339());
+            status.error = None;
+            log(&format!(
+                "re-assumed role for profile '{}', expires at {}.",
+                name,
+                credential.map_or("-".to_string(), |c| c.local_expired_at_str())
+            ));
+            true
+        }
+        Ok(_) => {
+            status.state = RefreshState::Failed;
+            status.error = Some("the session was not re-issued.".to_string());
+            false
+        }
+        Err(e) => {
+            log(&format!(
+                "unable to re-assume role for profile '{}'. {}",
+                name, e
+            ));
+            status.state = RefreshState::Failed;
+            status.error = Some(e.to_string());
+            false
+        }
+    }
+}
+
+/// Waits for Ctrl-C, or SIGTERM of service managers.
+#[cfg(unix)]
+async fn stopped() {
+    use tokio::signal::unix::{signal, SignalKind};
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => (),
+                _ = terminate.recv() => (),
+            }
+        }
+        Err(_) => {
+            tokio::signal::ctrl_c().await.ok();
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn stopped() {
+    tokio::signal::ctrl_c().await.ok();
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+#[cfg(unix)]
+type Listener = tokio::net::UnixListener;
+
+#[cfg(not(unix))]
+type Listener = ();
+
+/// Binds the socket, replacing the one left by a stopped daemon. Only the user can connect to it.
+#[cfg(unix)]
+fn listen(socket: &Path) -> Result<Listener> {
+    use std::os::unix::fs::PermissionsExt;
+    if socket.exists() {
+        if std::os::unix::net::UnixStream::connect(socket).is_ok() {
+            return Err(ServerError(format!(
+                "another daemon is running at {}.",
+                socket.display()
+            )));
+        }
+        std::fs::remove_file(socket).map_err(|e| ServerError(e.to_string()))?;
+    }
+    let listener = tokio::net::UnixListener::bind(socket)
+        .map_err(|e| ServerError(format!("{}: {}", socket.display(), e)))?;
+    std::fs::set_permissions(socket, std::fs::Permissions::from_mode(0o600))
+        .map_err(|e| ServerError(e.to_string()))?;
+    Ok(listener)
+}
+
+#[cfg(not(unix))]
+fn listen(_: &Path) -> Result<Listener> {
+    Err(ServerError("daemon requires unix sockets.".to_string()))
+}
+
+#[cfg(unix)]
+async fn serve_status(mut listener: Listener, status: Arc<Mutex<DaemonStatus>>) {
+    use tokio::io::AsyncWriteExt;
+    while let Ok((mut stream, _)) = listener.accept().await {
+        let body = serde_json::to_string(&*status.lock().await).unwrap_or_default();
+        stream.write_all(body.as_bytes()).await.ok();
+    }
+}
+
+#[cfg(not(unix))]
+async fn serve_status(_: Listener, _: Arc<Mutex<DaemonStatus>>) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{AccessKey, AssumedRole, Credential, Profile};
+
+    fn session(hours: i64) -> Credential {
+        Credential {
+            access_key_id: "ASIA".to_string(),
+            expiration: Utc::now() + Duration::hours(hours),
+            secret_access_key: "secret".to_string(),
+            session_token: "token".to_string(),
+        }
+    }
+
+    fn profile_map(source_hours: i64, role_hours: i64) -> ProfileMap {
+        let mut profile_map = ProfileMap::new();
+        profile_map.insert(
+            "base",
+            Profile {
+                profile_name: "base".to_string(),
+                access: Some(Access::AccessKey(AccessKey {
+                    mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+                    ..AccessKey::default()
+                })),
+                credential: Some(session(source_hours)),
+                ..Profile::default()
+            },
+        );
+        profile_map.insert(
+            "admin",
+            Profile {
+                profile_name: "admin".to_string(),
+                access: Some(Access::AssumeRole(AssumedRole {
+                    role_arn: "arn:aws:iam::000000000000:role/admin".parse().unwrap(),
+                    mfa_serial: Some("arn:aws:iam::000000000000:mfa/me".to_string()),
+                    source_profile: "base".to_string(),
+                })),
+                credential: Some(session(role_hours)),
+                ..Profile::default()
+            },
+        );
+        profile_map
+    }
+
+    fn status() -> ProfileStatus {
+        ProfileStatus {
+            profile: "admin".to_string(),
+            state: RefreshState::Failed,
+            expiration: None,
+            refreshed_at: None,
+            error: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn fresh_session() {
+        let mut status = status();
+        let mut profile_map = profile_map(-1, 1);
+        assert!(!refresh(&mut profile_map, Duration::minutes(15), &mut status).await);
+        assert_eq!(status.state, RefreshState::Fresh);
+        assert!(status.expiration.is_some());
+    }
+
+    #[tokio::test]
+    async fn waits_for_sign_in() {
+        let mut status = status();
+        let mut profile_map = profile_map(-1, 0);
+        assert!(!refresh(&mut profile_map, Duration::minutes(15), &mut status).await);
+        assert_eq!(status.state, RefreshState::WaitingForSignIn);
+        assert!(status.refreshed_at.is_none());
+    }
+
+    #[tokio::test]
+    async fn backs_off_after_failure() {
+        let mut status = ProfileStatus {
+            refreshed_at: Some(Utc::now().to_rfc3339()),
+            ..status()
+        };
+        let mut profile_map = profile_map(1, 0);
+        assert!(!refresh(&mut profile_map, Duration::minutes(15), &mut status).await);
+        assert_eq!(status.state, RefreshState::Failed);
+    }
+
+    #[test]
+    fn sessions_follow_the_file() {
+        let mut profile_map = profile_map(1, 1);
+        let mut saved = sessions(&profile_map);
+        assert_eq!(saved.len(), 2);
+        saved.remove("base");
+        load_sessions(&mut profile_map, &saved);
+        assert!(profile_map.get("base").unwrap().credential.is_none());
+        assert!(profile_map.get("admin").unwrap().credential.is_some());
+        load_sessions(&mut profile_map, &HashMap::new());
+        assert!(sessions(&profile_map).is_empty());
+    }
+
+    #[test]
+    fn status_json() {
+        let status = DaemonStatus {
+            pid: 1,
+            started_at: "2020-01-01T00:00:00+00:00".to_string(),
+            refresh_before: 15,
+            profiles: vec![ProfileStatus {
+                state: RefreshState::WaitingForSignIn,
+                ..status()
+            }],
+        };
+        let json = serde_json::to_string(&status).unwrap();
+        assert!(json.contains(r#""state":"waiting_for_sign_in""#));
+        let parsed: DaemonStatus = serde_json::from_str(&json).unwrap();
+        assert!(parsed.refreshes("admin"));
+        assert!(!parsed.refreshes("base"));
+    }
+}
@@ -0,0 +1,183 @@
+use super::*;
+use crate::profile::export;
+use crate::shell::Shell;
+use hyper::header::AUTHORIZATION;
+use serde::Serialize;
+
+const CREDENTIALS_PATH: &str = "/credentials";
+
+/// Response of the container credentials provider.
+/// https://docs.aws.amazon.com/sdkref/latest/guide/feature-container-credentials.html
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct ContainerCredential {
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
+}
+
+pub struct Ecs {
+    session: Session,
+    authorization_token: String,
+}
+
+/// Serves credentials of the profile with the protocol of `AWS_CONTAINER_CREDENTIALS_FULL_URI`,
+/// and prints the variables to set for the clients.
+pub async fn serve(
+    profile_map: ProfileMap,
+    profile_name: &str,
+    port: u16,
+    margin: Duration,
+    shell: Shell,
+) -> Result<()> {
+    let session = Session::new(profile_map, profile_name, margin);
+    session.credential().await?;
+    let state = Arc::new(Ecs {
+        session,
+        authorization_token: random_token(),
+    });
+
+    let token = state.authorization_token.clone();
+    let address = SocketAddr::from(([127, 0, 0, 1], port));
+    run(address, state, handle, |address| {
+        print!(
+            "{}",
+            export::rc(
+                shell,
+                vec![
+                    (
+                        "AWS_CONTAINER_CREDENTIALS_FULL_URI",
+                        format!("http://{}{}", address, CREDENTIALS_PATH),
+                    ),
+                    ("AWS_CONTAINER_AUTHORIZATION_TOKEN", token),
+                ],
+                vec![
+                    "AWS_ACCESS_KEY_ID",
+                    "AWS_SECRET_ACCESS_KEY",
+                    "AWS_SESSION_TOKEN",
+                    "AWS_PROFILE",
+                ],
+                vec![&format!(
+                    "serving credentials for profile '{}' at {}",
+                    profile_name, address
+                )],
+            )
+        );
+    })
+    .await
+}
+
+async fn handle(state: Arc<Ecs>, request: Request<Body>) -> Response<Body> {
+    if request.uri().path() != CREDENTIALS_PATH {
+        return response(StatusCode::NOT_FOUND, "not found".to_string());
+    }
+
+    let authorized = request
+        .headers()
+        .get(AUTHORIZATION)
+        .is_some_and(|value| same_token(value.as_bytes(), state.authorization_token.as_bytes()));
+    if !authorized {
+        log("request with invalid authorization token is rejected.");
+        return response(StatusCode::UNAUTHORIZED, "unauthorized".to_string());
+    }
+
+    match state.session.credential().await {
+        Ok(credential) => {
+            let body = serde_json::to_string(&ContainerCredential {
+                access_key_id: credential.access_key_id,
+                secret_access_key: credential.secret_access_key,
+                token: credential.session_token,
+                expiration: credential.expiration,
+            });
+            match body {
+                Ok(body) => response(StatusCode::OK, body),
+                Err(e) => error_response(&ServerError(e.to_string())),
+            }
+        }
+        Err(e) => error_response(&e),
+    }
+}
+
+/// Compares the tokens in constant time, so that the time of the response doesn't leak the token.
+fn same_token(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{Credential, Profile};
+    use chrono::Utc;
+
+    fn state(life: Duration) -> Arc<Ecs> {
+        let mut profile_map = ProfileMap::new();
+        profile_map.insert(
+            "foo",
+            Profile {
+                profile_name: "foo".to_string(),
+                credential: Some(Credential {
+                    access_key_id: "ASIA".to_string(),
+                    expiration: Utc::now() + life,
+                    secret_access_key: "secret".to_string(),
+                    session_token: "token".to_string(),
+                }),
+                ..Profile::default()
+            },
+        );
+        Arc::new(Ecs {
+            session: Session::new(profile_map, "foo", Duration::zero()),
+            authorization_token: "auth".to_string(),
+        })
+    }
+
+    fn request(path: &str, token: &str) -> Request<Body> {
+        Request::get(path)
+            .header(AUTHORIZATION, token)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn serves_credential() {
+        let response = handle(state(Duration::hours(1)), request("/credentials", "auth")).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = body(response).await;
+        assert!(body.starts_with(
+            r#"{"AccessKeyId":"ASIA","SecretAccessKey":"secret","Token":"token","Expiration":""#
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_invalid_token() {
+        let response = handle(state(Duration::hours(1)), request("/credentials", "x")).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[tokio::test]
+    async fn expired_session_is_error() {
+        let response = handle(state(Duration::hours(-1)), request("/credentials", "auth")).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[tokio::test]
+    async fn unknown_path() {
+        let response = handle(state(Duration::hours(1)), request("/other", "auth")).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn compare_tokens() {
+        assert!(same_token(b"auth", b"auth"));
+        assert!(!same_token(b"auth", b"autH"));
+        assert!(!same_token(b"auth", b"auth2"));
+        assert!(!same_token(b"", b"auth"));
+    }
+}
@@ -0,0 +1,313 @@
+use super::*;
+use crate::profile::export;
+use crate::shell::Shell;
+use chrono::{DateTime, SecondsFormat, Utc};
+use hyper::Method;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+const TOKEN_PATH: &str = "/latest/api/token";
+const TOKEN_TTL_HEADER: &str = "x-aws-ec2-metadata-token-ttl-seconds";
+const TOKEN_HEADER: &str = "x-aws-ec2-metadata-token";
+const MAX_TOKEN_TTL: i64 = 21600;
+const CREDENTIALS_PATH: &str = "/latest/meta-data/iam/security-credentials/";
+const REGION_PATH: &str = "/latest/meta-data/placement/region";
+const IDENTITY_DOCUMENT_PATH: &str = "/latest/dynamic/instance-identity/document";
+
+/// Response of `iam/security-credentials/<role>`.
+/// https://docs.aws.amazon.com/AWSEC2/latest/UserGuide/instancedata-data-categories.html
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct InstanceCredential {
+    code: &'static str,
+    last_updated: String,
+    #[serde(rename = "Type")]
+    credential_type: &'static str,
+    access_key_id: String,
+    secret_access_key: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    token: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expiration: Option<String>,
+}
+
+pub struct Imds {
+    session: Session,
+    region: Option<String>,
+    tokens: std::sync::Mutex<HashMap<String, DateTime<Utc>>>,
+}
+
+/// Parses `--address`. Only loopback and link-local addresses are accepted,
+/// as any client reaching the address gets credentials with a PUT request.
+pub fn local_address(s: &str) -> std::result::Result<IpAddr, String> {
+    let address: IpAddr = s.parse().map_err(|e| format!("{}", e))?;
+    let local = match address {
+        IpAddr::V4(v4) => v4.is_loopback() || v4.is_link_local(),
+        IpAddr::V6(v6) => v6.is_loopback() || v6.is_unicast_link_local(),
+    };
+    if local {
+        Ok(address)
+    } else {
+        Err(format!(
+            "{} is neither loopback nor link-local, credentials would be served to the network.",
+            address
+        ))
+    }
+}
+
+/// Serves credentials and region of the profile with the protocol of EC2 instance metadata service v2,
+/// and prints the variables to set for the clients.
+pub async fn serve(
+    profile_map: ProfileMap,
+    profile_name: &str,
+    address: IpAddr,
+    port: u16,
+    margin: Duration,
+    shell: Shell,
+) -> Result<()> {
+    let region = profile_map.get(profile_name)?.region.clone();
+    let session = Session::new(profile_map, profile_name, margin);
+    session.credential().await?;
+    let state = Arc::new(Imds {
+        session,
+        region,
+        tokens: std::sync::Mutex::new(HashMap::new()),
+    });
+
+    run(SocketAddr::new(address, port), state, handle, |address| {
+        print!(
+            "{}",
+            export::rc(
+                shell,
+                vec![(
+                    "AWS_EC2_METADATA_SERVICE_ENDPOINT",
+                    format!("http://{}/", address),
+                )],
+                vec![
+                    "AWS_EC2_METADATA_DISABLED",
+                    "AWS_ACCESS_KEY_ID",
+                    "AWS_SECRET_ACCESS_KEY",
+                    "AWS_SESSION_TOKEN",
+                    "AWS_PROFILE",
+                ],
+                vec![&format!(
+                    "serving instance metadata for profile '{}' at {}",
+                    profile_name, address
+                )],
+            )
+        );
+    })
+    .await
+}
+
+async fn handle(state: Arc<Imds>, request: Request<Body>) -> Response<Body> {
+    let path = request.uri().path().to_owned();
+    if path == TOKEN_PATH {
+        return match request.method() {
+            &Method::PUT => state.issue_token(&request),
+            _ => response(StatusCode::METHOD_NOT_ALLOWED, "".to_string()),
+        };
+    }
+
+    if request.method() != Method::GET {
+        return response(StatusCode::METHOD_NOT_ALLOWED, "".to_string());
+    }
+    if !state.valid_token(&request) {
+        return response(StatusCode::UNAUTHORIZED, "".to_string());
+    }
+
+    let role_name = &state.session.profile_name;
+    if path == CREDENTIALS_PATH {
+        response(StatusCode::OK, role_name.to_owned())
+    } else if path == format!("{}{}", CREDENTIALS_PATH, role_name) {
+        state.credential().await
+    } else if path == REGION_PATH {
+        match &state.region {
+            Some(region) => response(StatusCode::OK, region.to_owned()),
+            None => response(StatusCode::NOT_FOUND, "".to_string()),
+        }
+    } else if path == IDENTITY_DOCUMENT_PATH {
+        match &state.region {
+            Some(region) => response(
+                StatusCode::OK,
+                serde_json::json!({ "region": region }).to_string(),
+            ),
+            None => response(StatusCode::NOT_FOUND, "".to_string()),
+        }
+    } else {
+        response(StatusCode::NOT_FOUND, "".to_string())
+    }
+}
+
+impl Imds {
+    fn issue_token(&self, request: &Request<Body>) -> Response<Body> {
+        let ttl = request
+            .headers()
+            .get(TOKEN_TTL_HEADER)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<i64>().ok())
+            .filter(|ttl| (1..=MAX_TOKEN_TTL).contains(ttl));
+        match ttl {
+            Some(ttl) => {
+                let token = random_token();
+                let mut tokens = self.tokens.lock().unwrap();
+                tokens.retain(|_, expiration| *expiration > Utc::now());
+                tokens.insert(token.clone(), Utc::now() + Duration::seconds(ttl));
+                let mut response = response(StatusCode::OK, token);
+                response
+                    .headers_mut()
+                    .insert(TOKEN_TTL_HEADER, hyper::header::HeaderValue::from(ttl));
+                response
+            }
+            None => response(StatusCode::BAD_REQUEST, "".to_string()),
+        }
+    }
+
+    fn valid_token(&self, request: &Request<Body>) -> bool {
+        let token = request
+            .headers()
+            .get(TOKEN_HEADER)
+            .and_then(|value| value.to_str().ok());
+        match token {
+            Some(token) => self
+                .tokens
+                .lock()
+                .unwrap()
+                .get(token)
+                .is_some_and(|expiration| *expiration > Utc::now()),
+            None => false,
+        }
+    }
+
+    async fn credential(&self) -> Response<Body> {
+        match self.session.credential().await {
+            Ok(credential) => {
+                let body = serde_json::to_string(&InstanceCredential {
+                    code: "Success",
+                    last_updated: Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+                    credential_type: "AWS-HMAC",
+                    access_key_id: credential.access_key_id,
+                    secret_access_key: credential.secret_access_key,
+                    token: credential.session_token,
+                    expiration: credential.expiration,
+                });
+                match body {
+                    Ok(body) => response(StatusCode::OK, body),
+                    Err(e) => error_response(&ServerError(e.to_string())),
+                }
+            }
+            Err(e) => error_response(&e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::profile::{Credential, Profile};
+
+    fn state(region: Option<&str>) -> Arc<Imds> {
+        let mut profile_map = ProfileMap::new();
+        profile_map.insert(
+            "foo",
+            Profile {
+                profile_name: "foo".to_string(),
+                credential: Some(Credential {
+                    access_key_id: "ASIA".to_string(),
+                    expiration: Utc::now() + Duration::hours(1),
+                    secret_access_key: "secret".to_string(),
+                    session_token: "token".to_string(),
+                }),
+                ..Profile::default()
+            },
+        );
+        Arc::new(Imds {
+            session: Session::new(profile_map, "foo", Duration::zero()),
+            region: region.map(|r| r.to_string()),
+            tokens: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    #[test]
+    fn local_addresses() {
+        for address in &["127.0.0.1", "169.254.169.254", "::1", "fe80::1"] {
+            assert!(local_address(address).is_ok(), "{}", address);
+        }
+        for address in &["0.0.0.0", "192.168.0.1", "::", "localhost"] {
+            assert!(local_address(address).is_err(), "{}", address);
+        }
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        let bytes = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        String::from_utf8(bytes.to_vec()).unwrap()
+    }
+
+    async fn token(state: &Arc<Imds>) -> String {
+        let request = Request::put(TOKEN_PATH)
+            .header(TOKEN_TTL_HEADER, "60")
+            .body(Body::empty())
+            .unwrap();
+        let response = handle(state.clone(), request).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        body(response).await
+    }
+
+    async fn get(state: &Arc<Imds>, path: &str, token: &str) -> Response<Body> {
+        let request = Request::get(path)
+            .header(TOKEN_HEADER, token)
+            .body(Body::empty())
+            .unwrap();
+        handle(state.clone(), request).await
+    }
+
+    #[tokio::test]
+    async fn credentials_with_token() {
+        let state = state(Some("ap-northeast-1"));
+        let token = token(&state).await;
+
+        let response = get(&state, CREDENTIALS_PATH, &token).await;
+        assert_eq!(body(response).await, "foo");
+
+        let response = get(&state, &format!("{}foo", CREDENTIALS_PATH), &token).await;
+        let body = body(response).await;
+        assert!(body.starts_with(r#"{"Code":"Success","LastUpdated":"#));
+        assert!(body.contains(r#""Type":"AWS-HMAC","AccessKeyId":"ASIA","SecretAccessKey":"secret","Token":"token","Expiration":"#));
+    }
+
+    #[tokio::test]
+    async fn region() {
+        let state = state(Some("ap-northeast-1"));
+        let token = token(&state).await;
+        assert_eq!(
+            body(get(&state, REGION_PATH, &token).await).await,
+            "ap-northeast-1"
+        );
+
+        let state = self::state(None);
+        let token = self::token(&state).await;
+        assert_eq!(
+            get(&state, REGION_PATH, &token).await.status(),
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[tokio::test]
+    async fn requests_without_valid_token_are_rejected() {
+        let state = state(None);
+        assert_eq!(
+            get(&state, CREDENTIALS_PATH, "invalid").await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+
+        let request = Request::put(TOKEN_PATH)
+            .header(TOKEN_TTL_HEADER, "21601")
+            .body(Body::empty())
+            .unwrap();
+        assert_eq!(
+            handle(state.clone(), request).await.status(),
+            StatusCode::BAD_REQUEST
+        );
+    }
+}
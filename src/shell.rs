@@ -0,0 +1,84 @@
+pub mod init;
+
+use crate::error::Error::*;
+use crate::error::*;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Shell dialect used to render scripts that are meant to be evaluated by the caller's shell.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Shell {
+    Posix,
+    Bash,
+    Zsh,
+    Fish,
+    Pwsh,
+    Nushell,
+    Csh,
+}
+
+impl Shell {
+    pub fn variants() -> [&'static str; 11] {
+        [
+            "sh",
+            "posix",
+            "bash",
+            "zsh",
+            "fish",
+            "pwsh",
+            "powershell",
+            "nu",
+            "nushell",
+            "csh",
+            "tcsh",
+        ]
+    }
+
+    /// Detects the shell from `$SHELL`. Falls back to Pwsh on Windows and POSIX sh elsewhere.
+    pub fn detect() -> Self {
+        std::env::var("SHELL")
+            .ok()
+            .and_then(|path| Self::from_path(&path))
+            .unwrap_or(if cfg!(windows) {
+                Shell::Pwsh
+            } else {
+                Shell::Posix
+            })
+    }
+
+    fn from_path(path: &str) -> Option<Self> {
+        let name = Path::new(path).file_stem()?.to_str()?;
+        Self::from_str(name).ok()
+    }
+}
+
+impl FromStr for Shell {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "sh" | "posix" | "dash" | "ksh" => Ok(Shell::Posix),
+            "bash" => Ok(Shell::Bash),
+            "zsh" => Ok(Shell::Zsh),
+            "fish" => Ok(Shell::Fish),
+            "pwsh" | "powershell" => Ok(Shell::Pwsh),
+            "nu" | "nushell" => Ok(Shell::Nushell),
+            "csh" | "tcsh" => Ok(Shell::Csh),
+            _ => Err(UnknownShellError(s.to_owned())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_path() {
+        assert_eq!(Shell::from_path("/usr/bin/fish"), Some(Shell::Fish));
+        assert_eq!(Shell::from_path("/bin/bash"), Some(Shell::Bash));
+        assert_eq!(Shell::from_path("/usr/local/bin/pwsh"), Some(Shell::Pwsh));
+        assert_eq!(Shell::from_path("/bin/tcsh"), Some(Shell::Csh));
+        assert_eq!(Shell::from_path("/usr/bin/xonsh"), None);
+    }
+}
@@ -0,0 +1,208 @@
+use crate::error::Error::*;
+use crate::error::*;
+use crate::opts::Opts;
+use crate::shell::Shell;
+use structopt::clap;
+use structopt::StructOpt;
+
+/// Wraps the command so that `in`, `login`, `export` and `clear env` change the current shell.
+/// Help and version are printed as they are.
+const POSIX_WRAPPER: &str = r#"ngydv() {
+  case " $* " in
+    *" -h "*|*" --help "*|*" -V "*|*" --version "*)
+      command ngydv "$@"
+      return $?
+      ;;
+  esac
+  case "$1" in
+    in|login|export)
+      local __ngydv_script
+      __ngydv_script="$(NGYDV_SHELL=__SHELL__ command ngydv "$@")" || return $?
+      [ -n "$__ngydv_script" ] || return 1
+      eval "$__ngydv_script"
+      ;;
+    clear)
+      if [ "$2" = env ]; then
+        eval "$(NGYDV_SHELL=__SHELL__ command ngydv "$@")"
+      else
+        command ngydv "$@"
+      fi
+      ;;
+    *)
+      command ngydv "$@"
+      ;;
+  esac
+}
+"#;
+
+const BASH_PROMPT: &str = r#"__ngydv_prompt() {
+  local segment
+  segment="$(NGYDV_SHELL=bash command ngydv prompt 2>/dev/null)"
+  [ -n "$segment" ] && printf '(%s) ' "$segment"
+}
+case "$PS1" in
+  *__ngydv_prompt*) ;;
+  *) PS1='$(__ngydv_prompt)'"$PS1" ;;
+esac
+"#;
+
+const ZSH_PROMPT: &str = r#"__ngydv_prompt() {
+  local segment
+  segment="$(NGYDV_SHELL=zsh command ngydv prompt 2>/dev/null)"
+  [ -n "$segment" ] && printf '(%s) ' "$segment"
+}
+setopt PROMPT_SUBST
+case "$PROMPT" in
+  *__ngydv_prompt*) ;;
+  *) PROMPT='$(__ngydv_prompt)'"$PROMPT" ;;
+esac
+"#;
+
+const FISH_WRAPPER: &str = r#"function ngydv --wraps ngydv
+    set -lx NGYDV_SHELL fish
+    if contains -- -h $argv; or contains -- --help $argv; or contains -- -V $argv; or contains -- --version $argv
+        command ngydv $argv
+        return $status
+    end
+    switch "$argv[1]"
+        case in login export
+            set -l __ngydv_script (command ngydv $argv | string collect)
+            or return $status
+            test -n "$__ngydv_script"; or return 1
+            echo $__ngydv_script | source
+        case clear
+            if test "$argv[2]" = env
+                command ngydv $argv | source
+            else
+                command ngydv $argv
+            end
+        case '*'
+            command ngydv $argv
+    end
+end
+"#;
+
+const FISH_PROMPT: &str = r#"if not functions -q __ngydv_original_prompt
+    functions -c fish_prompt __ngydv_original_prompt
+    function fish_prompt
+        set -l segment (NGYDV_SHELL=fish command ngydv prompt 2>/dev/null)
+        test -n "$segment"; and printf '(%s) ' "$segment"
+        __ngydv_original_prompt
+    end
+end
+"#;
+
+/// Prints script to evaluate from rc file of the shell.
+pub fn script(shell: Shell, prompt: bool) -> Result<String> {
+    let (wrapper, prompt_hook, completion_shell) = match shell {
+        Shell::Bash => (
+            POSIX_WRAPPER.replace("__SHELL__", "bash"),
+            BASH_PROMPT,
+            clap::Shell::Bash,
+        ),
+        Shell::Zsh => (
+            POSIX_WRAPPER.replace("__SHELL__", "zsh"),
+            ZSH_PROMPT,
+            clap::Shell::Zsh,
+        ),
+        Shell::Fish => (FISH_WRAPPER.to_string(), FISH_PROMPT, clap::Shell::Fish),
+        _ => return Err(UnsupportedShellError(format!("{:?}", shell))),
+    };
+
+    let mut ret = wrapper;
+    ret.push('\n');
+    ret.push_str(&completion(completion_shell));
+    if prompt {
+        ret.push('\n');
+        ret.push_str(prompt_hook);
+    }
+    Ok(ret)
+}
+
+fn completion(shell: clap::Shell) -> String {
+    let mut buf = vec![];
+    Opts::clap().gen_completions_to("ngydv", shell, &mut buf);
+    let completion = String::from_utf8_lossy(&buf).to_string();
+    match shell {
+        // the generated file is for $fpath, it calls the function instead of registering it.
+        clap::Shell::Zsh => format!(
+            "{}\n(( $+functions[compdef] )) && compdef _ngydv ngydv\n",
+            completion.trim_end().trim_end_matches("_ngydv \"$@\"")
+        ),
+        _ => completion,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bash_script() {
+        let script = script(Shell::Bash, true).unwrap();
+        assert!(script.contains("NGYDV_SHELL=bash command ngydv"));
+        assert!(script.contains("complete -F _ngydv"));
+        assert!(script.contains("PS1='$(__ngydv_prompt)'"));
+    }
+
+    #[test]
+    fn zsh_completion_is_registered_not_called() {
+        let script = script(Shell::Zsh, false).unwrap();
+        assert!(script.contains("compdef _ngydv ngydv"));
+        assert!(!script.contains("_ngydv \"$@\""));
+        assert!(!script.contains("__ngydv_prompt"));
+    }
+
+    #[test]
+    fn bash_script_syntax() {
+        let script = script(Shell::Bash, true).unwrap();
+        let output = std::process::Command::new("bash")
+            .arg("-n")
+            .arg("-c")
+            .arg(&script)
+            .output();
+        if let Ok(output) = output {
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn help_is_not_evaluated() {
+        // a stand-in printing a command, which the wrapper would run if it evaluated the output.
+        let dir = std::env::temp_dir().join(format!("ngydv-init-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let stand_in = dir.join("ngydv");
+        std::fs::write(&stand_in, "#!/bin/sh\necho 'echo evaluated'\n").unwrap();
+        std::process::Command::new("chmod")
+            .arg("+x")
+            .arg(&stand_in)
+            .status()
+            .unwrap();
+        let script = format!(
+            "PATH={}:$PATH\n{}\nngydv in --help\nngydv export -h\nngydv in x",
+            dir.display(),
+            POSIX_WRAPPER.replace("__SHELL__", "bash")
+        );
+        let output = std::process::Command::new("bash")
+            .arg("-c")
+            .arg(&script)
+            .output();
+        std::fs::remove_dir_all(&dir).ok();
+        if let Ok(output) = output {
+            assert_eq!(
+                String::from_utf8_lossy(&output.stdout),
+                "echo evaluated\necho evaluated\nevaluated\n"
+            );
+        }
+    }
+
+    #[test]
+    fn unsupported_shell() {
+        assert!(script(Shell::Csh, false).is_err());
+    }
+}